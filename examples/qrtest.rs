@@ -181,16 +181,16 @@ fn dump_data(data: &Data) {
 fn dump_cells(code: &Code) {
     let code = *code;
 
-    print!("    {} cells, corners:", code.size);
+    print!("    {}x{} cells, corners:", code.width, code.height);
     for u in 0..4 {
         print!(" ({},{})", code.corners[u].x, code.corners[u].y);
     }
     println!();
 
-    for v in 0..code.size {
+    for v in 0..code.height {
         print!("    ");
-        for u in 0..code.size {
-            let p = v * code.size + u;
+        for u in 0..code.width {
+            let p = v * code.width + u;
 
             if (code.cell_bitmap[(p >> 3) as usize] & (1 << (p & 7))) != 0 {
                 print!("[]");
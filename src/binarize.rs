@@ -0,0 +1,189 @@
+//! Conversion of grayscale images into the black and white pixels which
+//! the QR-code finder works on.
+
+use std::fmt::Debug;
+
+use crate::quirc::Pixel;
+
+/// Turns a grayscale image into black and white pixels.
+///
+/// Implement this to plug custom preprocessing into `Quirc::identify`.
+/// If your pipeline already produces a binary image, use
+/// `Quirc::identify_binary` instead.
+pub trait Binarizer: Debug + Send + Sync {
+    /// Binarize `image`, given as `width * height` bytes of grayscale,
+    /// into `pixels`. Each pixel should be set to 1 if it is dark and to
+    /// 0 if it is light. Any other non-zero value is taken as dark.
+    fn binarize(&self, image: &[u8], width: usize, height: usize, pixels: &mut [Pixel]);
+}
+
+/// A single global threshold, chosen using Otsu's method.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Otsu;
+
+impl Binarizer for Otsu {
+    fn binarize(&self, image: &[u8], width: usize, height: usize, pixels: &mut [Pixel]) {
+        FixedThreshold(otsu(image)).binarize(image, width, height, pixels);
+    }
+}
+
+/// A single global threshold. Pixels below the value are dark.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FixedThreshold(pub u8);
+
+impl Binarizer for FixedThreshold {
+    fn binarize(&self, image: &[u8], _width: usize, _height: usize, pixels: &mut [Pixel]) {
+        for (value, dest) in image.iter().zip(pixels.iter_mut()) {
+            *dest = if *value < self.0 { 1 } else { 0 };
+        }
+    }
+}
+
+/// A local threshold from the mean of a window around each pixel, which
+/// copes better with shadows and gradients across the image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AdaptiveThreshold {
+    /// The side of the averaging window is the larger image dimension
+    /// divided by this value.
+    pub window_divisor: usize,
+    /// Percentage below the local mean a pixel must be to count as dark.
+    pub bias: u8,
+}
+
+impl Default for AdaptiveThreshold {
+    fn default() -> Self {
+        Self {
+            window_divisor: 8,
+            bias: 5,
+        }
+    }
+}
+
+fn otsu(image: &[u8]) -> u8 {
+    let num_pixels = image.len();
+
+    // Calculate histogram
+    let mut histogram: [u32; 256] = [0; 256];
+
+    for value in image {
+        let value = *value as usize;
+        histogram[value] = histogram[value].wrapping_add(1);
+    }
+
+    // Calculate weighted sum of histogram values
+    let mut sum: u32 = 0;
+    for (i, val) in histogram.iter().enumerate() {
+        sum = sum.wrapping_add((i as u32).wrapping_mul(*val));
+    }
+
+    // Compute threshold
+    let mut sum_b: i32 = 0;
+    let mut q1: i32 = 0;
+    let mut max = 0_f64;
+    let mut threshold = 0_u8;
+    for (i, val) in histogram.iter().enumerate() {
+        // Weighted background
+        q1 = (q1 as u32).wrapping_add(*val) as i32;
+        if q1 == 0 {
+            continue;
+        }
+        // Weighted foreground
+        let q2 = num_pixels as i32 - q1;
+        if q2 == 0 {
+            break;
+        }
+        sum_b = (sum_b as u32).wrapping_add((i as u32).wrapping_mul(*val)) as i32;
+        let m1 = sum_b as f64 / q1 as f64;
+        let m2 = (sum as f64 - sum_b as f64) / q2 as f64;
+        let m1m2 = m1 - m2;
+        let variance = m1m2 * m1m2 * q1 as f64 * q2 as f64;
+        if variance >= max {
+            threshold = i as u8;
+            max = variance
+        }
+    }
+
+    threshold
+}
+
+/// Sum the pixels of a row over a sliding window of `2 * radius + 1`
+/// columns, clamped to the row.
+fn window_row_sums(row: &[u8], radius: usize, sums: &mut [u32]) {
+    let width = row.len();
+    let mut sum: u32 = row[..(radius + 1).min(width)]
+        .iter()
+        .map(|v| *v as u32)
+        .sum();
+
+    for (x, dest) in sums.iter_mut().enumerate() {
+        *dest = sum;
+        if x + radius + 1 < width {
+            sum += row[x + radius + 1] as u32;
+        }
+        if x >= radius {
+            sum -= row[x - radius] as u32;
+        }
+    }
+}
+
+// Local mean thresholding, in the style of Bradley and Roth. The window
+// sums are kept as running column totals, so only a single row of sums is
+// held in memory at any time.
+impl Binarizer for AdaptiveThreshold {
+    fn binarize(&self, image: &[u8], width: usize, height: usize, pixels: &mut [Pixel]) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let radius = (width.max(height) / self.window_divisor.max(1) / 2).max(1);
+        let bias = self.bias.min(100) as u64;
+
+        let mut row_sums = vec![0_u32; width];
+        let mut col_sums = vec![0_u64; width];
+
+        for y in 0..=radius.min(height - 1) {
+            window_row_sums(&image[y * width..(y + 1) * width], radius, &mut row_sums);
+            for (col, row) in col_sums.iter_mut().zip(row_sums.iter()) {
+                *col += *row as u64;
+            }
+        }
+
+        for y in 0..height {
+            let rows = (y + radius).min(height - 1) + 1 - y.saturating_sub(radius);
+            let row = &image[y * width..(y + 1) * width];
+            let dest = &mut pixels[y * width..(y + 1) * width];
+
+            for (x, ((value, dest), sum)) in row
+                .iter()
+                .zip(dest.iter_mut())
+                .zip(col_sums.iter())
+                .enumerate()
+            {
+                let cols = (x + radius).min(width - 1) + 1 - x.saturating_sub(radius);
+                let count = (rows * cols) as u64;
+
+                *dest = if *value as u64 * count * 100 < sum * (100 - bias) {
+                    1
+                } else {
+                    0
+                };
+            }
+
+            /* Slide the window down by one row */
+            if y + radius + 1 < height {
+                let y = y + radius + 1;
+                window_row_sums(&image[y * width..(y + 1) * width], radius, &mut row_sums);
+                for (col, row) in col_sums.iter_mut().zip(row_sums.iter()) {
+                    *col += *row as u64;
+                }
+            }
+            if y >= radius {
+                let y = y - radius;
+                window_row_sums(&image[y * width..(y + 1) * width], radius, &mut row_sums);
+                for (col, row) in col_sums.iter_mut().zip(row_sums.iter()) {
+                    *col -= *row as u64;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::identify::{grid_transform, perspective_map};
+use crate::quirc::*;
+use crate::render::Cell;
+
+/// An RGB image with three bytes per pixel, stored row by row. The
+/// pixels can be handed to an image library as they are, e.g. to
+/// `image::RgbImage::from_raw`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+const WHITE: [u8; 3] = [255, 255, 255];
+const BLACK: [u8; 3] = [64, 64, 64];
+const CORNER: [u8; 3] = [255, 0, 0];
+const CENTER: [u8; 3] = [0, 0, 255];
+const GROUPING: [u8; 3] = [0, 192, 0];
+const ALIGN: [u8; 3] = [255, 0, 255];
+const TIMING: [u8; 3] = [255, 160, 0];
+const DARK_CELL: [u8; 3] = [0, 96, 96];
+const LIGHT_CELL: [u8; 3] = [0, 255, 255];
+const ERASED_CELL: [u8; 3] = [255, 255, 0];
+
+impl RgbImage {
+    fn put(&mut self, x: i32, y: i32, color: [u8; 3]) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let i = (y as usize * self.width + x as usize) * 3;
+        self.pixels[i..i + 3].copy_from_slice(&color);
+    }
+
+    /// Draw a line from `a` to `b` with Bresenham's algorithm.
+    fn line(&mut self, a: Point, b: Point, color: [u8; 3]) {
+        let (dx, dy) = ((b.x - a.x).abs(), -(b.y - a.y).abs());
+        let (sx, sy) = ((b.x - a.x).signum(), (b.y - a.y).signum());
+        let (mut x, mut y) = (a.x, a.y);
+        let mut err = dx + dy;
+
+        loop {
+            self.put(x, y, color);
+            if x == b.x && y == b.y {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn cross(&mut self, p: Point, radius: i32, color: [u8; 3]) {
+        for d in -radius..=radius {
+            self.put(p.x + d, p.y, color);
+            self.put(p.x, p.y + d, color);
+        }
+    }
+}
+
+/// A colour for a region, spread over the hues so that neighbouring
+/// regions are easy to tell apart.
+fn region_color(region: usize) -> [u8; 3] {
+    let h = (region as u32).wrapping_mul(2_654_435_761);
+    [
+        128 + (h >> 24) as u8 / 2,
+        128 + (h >> 16) as u8 / 2,
+        128 + (h >> 8) as u8 / 2,
+    ]
+}
+
+impl Quirc {
+    /// Render the state of the detector after `identify` as an RGB image
+    /// the size of the input, to see at which stage a code was lost.
+    ///
+    /// The binarized pixels are drawn in white and dark gray, with each
+    /// region filled in its own pastel colour. Capstone corners are
+    /// marked with red crosses and their centres with blue ones. The
+    /// capstones of each grid are joined by green lines. For QR-codes the
+    /// timing scans are drawn in orange and the alignment estimate is
+    /// marked in magenta; Micro QR and rMQR grids have neither. Finally
+    /// the centre of each cell is projected back into the image and
+    /// drawn as a dot, coloured by the value extracted for the cell with
+    /// the configured `Sampling`: dark teal if it was read as dark, cyan
+    /// if it was read as light, and yellow if it was erased because it
+    /// lies outside the image.
+    ///
+    /// The pixels are those of the last pass, so with `Inversion::Both`
+    /// they show the inverted image and only the regions found in it. The
+    /// capstones and grids of both passes are drawn.
+    pub fn debug_image(&self) -> RgbImage {
+        let mut image = RgbImage {
+            width: self.w,
+            height: self.h,
+            pixels: Vec::with_capacity(self.w * self.h * 3),
+        };
+
+        for pixel in &self.pixels {
+            let color = match *pixel {
+                0 => WHITE,
+                1 => BLACK,
+                region => region_color(region as usize),
+            };
+            image.pixels.extend_from_slice(&color);
+        }
+
+        for cap in &self.capstones {
+            for corner in &cap.corners {
+                image.cross(*corner, 2, CORNER);
+            }
+            image.cross(cap.center, 3, CENTER);
+        }
+
+        for (index, qr) in self.grids.iter().enumerate() {
+            let centers = qr.caps.map(|cap| self.capstones[cap].center);
+            image.line(centers[0], centers[1], GROUPING);
+            image.line(centers[1], centers[2], GROUPING);
+
+            if qr.kind == CodeKind::Qr {
+                /* Timing scans run from the corner capstone */
+                image.line(qr.tpep[1], qr.tpep[2], TIMING);
+                image.line(qr.tpep[1], qr.tpep[0], TIMING);
+
+                image.cross(qr.align, 3, ALIGN);
+            }
+
+            let code = match self.extract(index) {
+                Ok(code) => code,
+                Err(_) => continue,
+            };
+            for y in 0..code.height {
+                for x in 0..code.width {
+                    let (u, v) = (x as f64 + 0.5, y as f64 + 0.5);
+                    let mut p = Point::default();
+                    perspective_map(grid_transform(qr, u, v), u, v, &mut p);
+                    let color = match code.render_cell(x, y) {
+                        Cell::Dark => DARK_CELL,
+                        Cell::Light => LIGHT_CELL,
+                        Cell::Erased => ERASED_CELL,
+                    };
+                    image.put(p.x, p.y, color);
+                }
+            }
+        }
+
+        image
+    }
+}
@@ -1,20 +1,47 @@
 #![allow(clippy::many_single_char_names)]
 
-use num_traits::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive;
 use std::convert::TryFrom;
 
+use crate::gs1::GS;
 use crate::quirc::*;
 use crate::version_db::*;
 use crate::DecodeError;
 
-#[derive(Copy, Clone)]
-struct Datastream {
-    raw: [u8; 8896],
+#[derive(Clone)]
+pub(crate) struct Datastream {
+    /// The codewords as read from the grid. Once error correction has
+    /// succeeded, they hold the corrected values.
+    pub(crate) raw: [u8; 8896],
+    /// Bits of `raw` which were read from erased cells.
+    erased: [u8; 8896],
+    /// Whether to record where the codewords of each block lie in
+    /// `raw`, which is only needed for grading.
+    pub(crate) record_bits: bool,
+    /// The error correction applied to each block.
+    pub(crate) blocks: Vec<BlockCorrection>,
     data_bits: i32,
     ptr: i32,
     data: [u8; 8896],
 }
 
+/// The result of error correction for one block of codewords.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BlockCorrection {
+    /// The number of codewords in the block.
+    pub(crate) codewords: usize,
+    /// The first bit in `Datastream::raw` and the length in bits of each
+    /// codeword of the block, if `Datastream::record_bits` is set.
+    pub(crate) bits: Vec<(i32, i32)>,
+    /// The number of ECC codewords.
+    pub(crate) ecc_codewords: usize,
+    /// The indices of the codewords which were corrected without being
+    /// erased.
+    pub(crate) errors: Vec<usize>,
+    /// The number of erased codewords used for correction.
+    pub(crate) erasures: usize,
+}
+
 /// Galois Field.
 #[derive(Copy, Clone)]
 struct GaloisField {
@@ -36,7 +63,7 @@ static GF16: GaloisField = GaloisField {
     exp: &GF16_EXP,
 };
 
-static GF256_EXP: [u8; 256] = [
+pub(crate) static GF256_EXP: [u8; 256] = [
     0x1, 0x2, 0x4, 0x8, 0x10, 0x20, 0x40, 0x80, 0x1d, 0x3a, 0x74, 0xe8, 0xcd, 0x87, 0x13, 0x26,
     0x4c, 0x98, 0x2d, 0x5a, 0xb4, 0x75, 0xea, 0xc9, 0x8f, 0x3, 0x6, 0xc, 0x18, 0x30, 0x60, 0xc0,
     0x9d, 0x27, 0x4e, 0x9c, 0x25, 0x4a, 0x94, 0x35, 0x6a, 0xd4, 0xb5, 0x77, 0xee, 0xc1, 0x9f, 0x23,
@@ -54,7 +81,7 @@ static GF256_EXP: [u8; 256] = [
     0x12, 0x24, 0x48, 0x90, 0x3d, 0x7a, 0xf4, 0xf5, 0xf7, 0xf3, 0xfb, 0xeb, 0xcb, 0x8b, 0xb, 0x16,
     0x2c, 0x58, 0xb0, 0x7d, 0xfa, 0xe9, 0xcf, 0x83, 0x1b, 0x36, 0x6c, 0xd8, 0xad, 0x47, 0x8e, 0x1,
 ];
-static GF256_LOG: [u8; 256] = [
+pub(crate) static GF256_LOG: [u8; 256] = [
     0, 0xff, 0x1, 0x19, 0x2, 0x32, 0x1a, 0xc6, 0x3, 0xdf, 0x33, 0xee, 0x1b, 0x68, 0xc7, 0x4b, 0x4,
     0x64, 0xe0, 0xe, 0x34, 0x8d, 0xef, 0x81, 0x1c, 0xc1, 0x69, 0xf8, 0xc8, 0x8, 0x4c, 0x71, 0x5,
     0x8a, 0x65, 0x2f, 0xe1, 0x24, 0xf, 0x21, 0x35, 0x93, 0x8e, 0xda, 0xf0, 0x12, 0x82, 0x45, 0x1d,
@@ -121,18 +148,29 @@ fn poly_eval(s: &[u8], x: u8, gf: &GaloisField) -> u8 {
 }
 
 /// Berlekamp-Massey algorithm for finding error locator polynomials.
+///
+/// The search starts from the erasure locator `gamma`, whose roots are
+/// the `erasures` known error locations, so that the result locates
+/// both erasures and errors.
 #[allow(non_snake_case)]
-fn berlekamp_massey(s: &[u8], n: usize, gf: &GaloisField, sigma: &mut [u8]) {
+fn berlekamp_massey(
+    s: &[u8],
+    n: usize,
+    gf: &GaloisField,
+    gamma: &[u8],
+    erasures: usize,
+    sigma: &mut [u8],
+) {
     let mut C: [u8; 64] = [0; 64];
     let mut B: [u8; 64] = [0; 64];
-    let mut L = 0;
+    let mut L = erasures;
     let mut m = 1;
     let mut b = 1;
 
-    B[0] = 1;
-    C[0] = 1;
+    B[..64].copy_from_slice(&gamma[..64]);
+    C[..64].copy_from_slice(&gamma[..64]);
 
-    for n in 0..n {
+    for n in erasures..n {
         let mut d = s[n];
         for i in 1..=L {
             if C[i] as i32 != 0 && s[n - i] as i32 != 0 {
@@ -149,11 +187,11 @@ fn berlekamp_massey(s: &[u8], n: usize, gf: &GaloisField, sigma: &mut [u8]) {
 
         if d == 0 {
             m += 1
-        } else if L * 2 <= n {
+        } else if L * 2 <= n + erasures {
             let T = C;
             poly_add(&mut C, &B, mult, m, gf);
             B.copy_from_slice(&T);
-            L = n + 1 - L;
+            L = n + 1 + erasures - L;
             b = d;
             m = 1
         } else {
@@ -218,9 +256,74 @@ fn eloc_poly(omega: &mut [u8], s: &[u8], sigma: &[u8], npar: usize) {
     }
 }
 
-fn correct_block(data: &mut [u8], ecc: &RsParams) -> Result<(), DecodeError> {
+/// Compute the erasure locator polynomial, which has a root at the
+/// inverse of the location of each erased codeword. Erasures are given
+/// as indices into the block.
+fn erasure_locator(bs: i32, erasures: &[usize], gamma: &mut [u8]) {
+    for val in gamma.iter_mut().take(64) {
+        *val = 0;
+    }
+    gamma[0] = 1;
+
+    for (n, index) in erasures.iter().enumerate() {
+        /* Multiply by (1 + X x), where X = alpha^location */
+        let log_x = (bs as usize - index - 1) % 255;
+        for i in (1..=n + 1).rev() {
+            let c = gamma[i - 1];
+            if c != 0 {
+                gamma[i] ^= GF256_EXP[(GF256_LOG[c as usize] as usize + log_x) % 255];
+            }
+        }
+    }
+}
+
+/// Correct a block, given the indices of codewords which are known to be
+/// unreliable. Each erasure uses up one ECC codeword, while an error at
+/// an unknown location uses up two.
+///
+/// Erased codewords may well have been read correctly, so if the block
+/// can't be corrected with them, it is corrected as if there were none.
+fn correct_block(
+    data: &mut [u8],
+    ecc: &RsParams,
+    erasures: &[usize],
+) -> Result<BlockCorrection, DecodeError> {
+    let bs = ecc.bs as usize;
+    let mut read = [0; 256];
+    read[..bs].copy_from_slice(&data[..bs]);
+    let mut used: &[usize] = &[];
+    if !erasures.is_empty() && erasures.len() <= bs - ecc.dw as usize {
+        let mut block = read;
+        if correct_erasures(&mut block[..bs], ecc, erasures).is_ok() {
+            data[..bs].copy_from_slice(&block[..bs]);
+            used = erasures;
+        }
+    }
+    if used.is_empty() {
+        correct_erasures(data, ecc, &[])?;
+    }
+
+    let errors = (0..bs)
+        .filter(|i| data[*i] != read[*i] && !used.contains(i))
+        .collect();
+
+    Ok(BlockCorrection {
+        codewords: bs,
+        ecc_codewords: bs - ecc.dw as usize,
+        errors,
+        erasures: used.len(),
+        ..Default::default()
+    })
+}
+
+fn correct_erasures(
+    data: &mut [u8],
+    ecc: &RsParams,
+    erasures: &[usize],
+) -> Result<(), DecodeError> {
     let npar = ecc.bs as usize - ecc.dw as usize;
     let mut s: [u8; 64] = [0; 64];
+    let mut gamma: [u8; 64] = [0; 64];
     let mut sigma: [u8; 64] = [0; 64];
     let mut sigma_deriv: [u8; 64] = [0; 64];
     let mut omega: [u8; 64] = [0; 64];
@@ -228,7 +331,8 @@ fn correct_block(data: &mut [u8], ecc: &RsParams) -> Result<(), DecodeError> {
     if block_syndromes(data, ecc.bs, npar, &mut s) == 0 {
         return Ok(());
     }
-    berlekamp_massey(&s, npar, &GF256, &mut sigma);
+    erasure_locator(ecc.bs, erasures, &mut gamma);
+    berlekamp_massey(&s, npar, &GF256, &gamma, erasures.len(), &mut sigma);
     /* Compute derivative of sigma */
     let mut i = 0;
     while i + 1 < 64 {
@@ -245,6 +349,11 @@ fn correct_block(data: &mut [u8], ecc: &RsParams) -> Result<(), DecodeError> {
         if poly_eval(&sigma, xinv, &GF256) == 0 {
             let sd_x = poly_eval(&sigma_deriv, xinv, &GF256);
             let omega_x = poly_eval(&omega, xinv, &GF256);
+            /* An erased codeword may have been read correctly */
+            if omega_x == 0 {
+                i += 1;
+                continue;
+            }
             let error = GF256_EXP[((255 - GF256_LOG[sd_x as usize] as i32
                 + GF256_LOG[omega_x as usize] as i32)
                 % 255) as usize];
@@ -283,7 +392,9 @@ fn format_syndromes(u: u16, s: &mut [u8]) -> i32 {
     nonzero
 }
 
-fn correct_format(f_ret: &mut u16) -> Result<(), DecodeError> {
+/// Correct the format information, returning the number of bits which
+/// were corrected.
+pub(crate) fn correct_format(f_ret: &mut u16) -> Result<u32, DecodeError> {
     let mut u: u16 = *f_ret;
     let mut s: [u8; 64] = [0; 64];
     let mut sigma: [u8; 64] = [0; 64];
@@ -292,9 +403,11 @@ fn correct_format(f_ret: &mut u16) -> Result<(), DecodeError> {
      * to get S_1 .. S_6 (but we index them from 0).
      */
     if format_syndromes(u, &mut s) == 0 {
-        return Ok(());
+        return Ok(0);
     }
-    berlekamp_massey(&s, 3 * 2, &GF16, &mut sigma);
+    let mut gamma: [u8; 64] = [0; 64];
+    gamma[0] = 1;
+    berlekamp_massey(&s, 3 * 2, &GF16, &gamma, 0, &mut sigma);
 
     /* Now, find the roots of the polynomial */
     for i in 0..15 {
@@ -306,37 +419,86 @@ fn correct_format(f_ret: &mut u16) -> Result<(), DecodeError> {
     if format_syndromes(u, &mut s) != 0 {
         return Err(DecodeError::FormatEcc);
     }
+    let errors = (*f_ret ^ u).count_ones();
     *f_ret = u;
 
-    Ok(())
+    Ok(errors)
+}
+
+/// Correct the format information of a Micro QR-code, returning the
+/// symbol number, the mask and the number of corrected bits.
+pub(crate) fn correct_micro_format(bits: u16) -> Result<(usize, i32, u32), DecodeError> {
+    let mut format = bits ^ 0x4445;
+
+    let errors = correct_format(&mut format)?;
+
+    let fdata = format as i32 >> 10;
+    Ok(((fdata >> 2) as usize, fdata & 3, errors))
+}
+
+/// The masks applied to the two copies of the rMQR format information.
+pub(crate) static RMQR_FORMAT_MASK: [u32; 2] = [0x1fab2, 0x20a7b];
+
+/// Correct an unmasked copy of the rMQR format information, which is a
+/// BCH(18,6) codeword like QR-code version information. Returns the
+/// ECC level and the version.
+pub(crate) fn correct_rmqr_format(bits: u32) -> Result<(EccLevel, usize, u32), DecodeError> {
+    let (fdata, errors) = (0..64)
+        .map(|fdata| (fdata, (version_info_bits(fdata) ^ bits).count_ones()))
+        .filter(|(_, distance)| *distance <= 3)
+        .min_by_key(|(_, distance)| *distance)
+        .ok_or(DecodeError::FormatEcc)?;
+
+    let ecc_level = if fdata & 0x20 != 0 {
+        EccLevel::H
+    } else {
+        EccLevel::M
+    };
+    Ok((ecc_level, (fdata & 0x1f) + RMQR_VERSION_MIN, errors))
+}
+
+/// The cell holding bit `n` of a copy of the rMQR format information:
+/// 0 is the copy next to the finder pattern, 1 the copy next to the
+/// finder sub-pattern.
+pub(crate) fn rmqr_format_cell(width: i32, height: i32, which: usize, n: i32) -> (i32, i32) {
+    match (which, n) {
+        (0, 0..=14) => (8 + n / 5, 1 + n % 5),
+        (0, _) => (11, n - 14),
+        (_, 0..=14) => (width - 8 + n / 5, height - 6 + n % 5),
+        (_, _) => (width - 20 + n, height - 6),
+    }
 }
 
 #[inline]
 fn grid_bit(code: &Code, x: i32, y: i32) -> i32 {
-    let p: i32 = y * code.size + x;
+    let p: i32 = y * code.width + x;
     code.cell_bitmap[(p >> 3) as usize] as i32 >> (p & 7) & 1
 }
 
+#[inline]
+fn grid_erased(code: &Code, x: i32, y: i32) -> bool {
+    let p: i32 = y * code.width + x;
+    code.erasure_bitmap[(p >> 3) as usize] >> (p & 7) & 1 != 0
+}
+
 fn read_format(code: &Code, data: &mut Data, which: i32) -> Result<(), DecodeError> {
     let mut format = 0_u16;
     if which != 0 {
         for i in 0..7 {
-            format = ((format as i32) << 1 | grid_bit(code, 8, code.size - 1 - i)) as u16;
+            format = ((format as i32) << 1 | grid_bit(code, 8, code.width - 1 - i)) as u16;
         }
         for i in 0..8 {
-            format = ((format as i32) << 1 | grid_bit(code, code.size - 8 + i, 8)) as u16;
+            format = ((format as i32) << 1 | grid_bit(code, code.width - 8 + i, 8)) as u16;
         }
     } else {
-        static XS: [i32; 15] = [8, 8, 8, 8, 8, 8, 8, 8, 7, 5, 4, 3, 2, 1, 0];
-        static YS: [i32; 15] = [0, 1, 2, 3, 4, 5, 7, 8, 8, 8, 8, 8, 8, 8, 8];
-
-        for i in (0..=14).rev() {
-            format = ((format as i32) << 1 | grid_bit(code, XS[i as usize], YS[i as usize])) as u16;
+        for (x, y) in FORMAT_CELLS.iter().rev() {
+            format = ((format as i32) << 1 | grid_bit(code, *x, *y)) as u16;
         }
     }
     format ^= 0x5412;
 
-    correct_format(&mut format)?;
+    data.format_errors = correct_format(&mut format)?;
+    data.format_copy = which as usize;
 
     let fdata = (format as i32 >> 10) as u16;
     data.ecc_level = EccLevel::from_i32(fdata as i32 >> 3).unwrap();
@@ -345,7 +507,74 @@ fn read_format(code: &Code, data: &mut Data, which: i32) -> Result<(), DecodeErr
     Ok(())
 }
 
-fn mask_bit(mask: i32, i: i32, j: i32) -> i32 {
+/// Micro QR-codes have a single copy of the format information, next to
+/// the finder pattern.
+fn read_micro_format(code: &Code, data: &mut Data) -> Result<(), DecodeError> {
+    let mut format = 0_u16;
+    for i in 1..=8 {
+        format = ((format as i32) << 1 | grid_bit(code, i, 8)) as u16;
+    }
+    for i in (1..=7).rev() {
+        format = ((format as i32) << 1 | grid_bit(code, 8, i)) as u16;
+    }
+
+    let (symbol, mask, errors) = correct_micro_format(format)?;
+    let info = &MICRO_SYMBOL_DB[symbol];
+    /* A symbol number for another size is no more use than an
+     * uncorrectable format, and likely means the code is mirrored.
+     */
+    if info.version as i32 * 2 + 9 != code.width {
+        return Err(DecodeError::FormatEcc);
+    }
+
+    data.version = info.version;
+    data.ecc_level = info.ecc_level;
+    data.mask = mask;
+    data.format_errors = errors;
+
+    Ok(())
+}
+
+fn read_rmqr_format(code: &Code, data: &mut Data, which: usize) -> Result<(), DecodeError> {
+    let mut format = 0_u32;
+    for n in (0..18).rev() {
+        let (x, y) = rmqr_format_cell(code.width, code.height, which, n);
+        format = format << 1 | grid_bit(code, x, y) as u32;
+    }
+
+    let (ecc_level, version, errors) = correct_rmqr_format(format ^ RMQR_FORMAT_MASK[which])?;
+    let info = &RMQR_VERSION_DB[version - 1];
+    if info.width != code.width || info.height != code.height {
+        return Err(DecodeError::FormatEcc);
+    }
+
+    data.version = version;
+    data.ecc_level = ecc_level;
+    data.format_copy = which;
+    data.format_errors = errors;
+
+    Ok(())
+}
+
+/// Read one of the two version information blocks: 0 is to the left of
+/// the top-right capstone, 1 is above the bottom-left capstone.
+fn read_version(code: &Code, which: i32) -> Option<usize> {
+    let mut bits = 0_u32;
+
+    for i in (0..18).rev() {
+        let (a, b) = (code.width - 11 + i % 3, i / 3);
+        let bit = if which != 0 {
+            grid_bit(code, b, a)
+        } else {
+            grid_bit(code, a, b)
+        };
+        bits = bits << 1 | bit as u32;
+    }
+
+    correct_version_info(bits)
+}
+
+pub(crate) fn mask_bit(mask: i32, i: i32, j: i32) -> i32 {
     match mask {
         0 => ((i + j) % 2 == 0) as i32,
         1 => (i % 2 == 0) as i32,
@@ -359,6 +588,59 @@ fn mask_bit(mask: i32, i: i32, j: i32) -> i32 {
     }
 }
 
+/// The QR-code mask pattern applied to the data.
+pub(crate) fn data_mask(data: &Data) -> i32 {
+    match data.kind {
+        CodeKind::Qr => data.mask,
+        /* Micro QR-codes use a subset of the QR-code patterns */
+        CodeKind::MicroQr => [1, 4, 6, 7][data.mask as usize & 3],
+        /* rMQR codes always use the same pattern */
+        CodeKind::Rmqr => 4,
+    }
+}
+
+fn micro_reserved_cell(i: i32, j: i32) -> i32 {
+    /* Finder, separators, format and timing patterns */
+    (i == 0 || j == 0 || i < 9 && j < 9) as i32
+}
+
+fn reserved(data: &Data, i: i32, j: i32) -> i32 {
+    match data.kind {
+        CodeKind::Qr => reserved_cell(data.version, i, j),
+        CodeKind::MicroQr => micro_reserved_cell(i, j),
+        CodeKind::Rmqr => rmqr_reserved_cell(data.version, i, j),
+    }
+}
+
+fn rmqr_reserved_cell(version: usize, i: i32, j: i32) -> i32 {
+    let info = &RMQR_VERSION_DB[version - 1];
+    let (w, h) = (info.width, info.height);
+    /* Timing patterns along the edges */
+    if i == 0 || j == 0 || i == h - 1 || j == w - 1 {
+        return 1;
+    }
+    /* Finder + format: left */
+    if i < 8 && j < 8 || i < 6 && j < 11 || i < 4 && j == 11 {
+        return 1;
+    }
+    /* Finder sub-pattern + format: bottom right */
+    if i >= h - 5 && j >= w - 5 || i >= h - 6 && j >= w - 8 && j < w - 2 {
+        return 1;
+    }
+    /* Corner finder patterns */
+    if i == 1 && j == w - 2 || h > 9 && i == h - 2 && j == 1 {
+        return 1;
+    }
+    /* Alignment patterns, joined by vertical timing patterns */
+    for &p in info.apat.iter().take_while(|p| **p != 0) {
+        if j == p || (p - j).abs() < 2 && (i < 3 || i >= h - 3) {
+            return 1;
+        }
+    }
+
+    0
+}
+
 fn reserved_cell(version: usize, i: i32, j: i32) -> i32 {
     let ver = &VERSION_DB[version];
     let size = version as i32 * 4 + 17;
@@ -424,43 +706,70 @@ fn read_bit(code: &Code, data: &mut Data, ds: &mut Datastream, i: i32, j: i32) {
     let bitpos: i32 = ds.data_bits & 7;
     let bytepos: i32 = ds.data_bits >> 3;
     let mut v: i32 = grid_bit(code, j, i);
-    if mask_bit(data.mask, i, j) != 0 {
+    if mask_bit(data_mask(data), i, j) != 0 {
         v ^= 1
     }
     if v != 0 {
         ds.raw[bytepos as usize] = (ds.raw[bytepos as usize] as i32 | 0x80 >> bitpos) as u8;
     }
+    if grid_erased(code, j, i) {
+        ds.erased[bytepos as usize] |= 0x80 >> bitpos;
+    }
     ds.data_bits += 1;
 }
 
-fn read_data(code: &Code, data: &mut Data, ds: &mut Datastream) {
-    let mut y: i32 = code.size - 1;
-    let mut x: i32 = code.size - 1;
+/// The cells holding the codewords of a code of the given size, in
+/// order, as `(x, y)`.
+pub(crate) fn data_cells(data: &Data, size: i32, height: i32) -> Vec<(i32, i32)> {
+    let mut cells = Vec::new();
+    let mut y: i32 = height - 1;
+    let mut x: i32 = size - 1;
     let mut dir: i32 = -1;
+    /* The right edge of an rMQR code is a timing pattern, and the
+     * columns are paired from the one next to it.
+     */
+    if data.kind == CodeKind::Rmqr {
+        x -= 1;
+    }
     while x > 0 {
-        if x == 6 {
+        /* Skip the vertical timing pattern */
+        if x == 6 && data.kind == CodeKind::Qr {
             x -= 1
         }
-        if reserved_cell(data.version, y, x) == 0 {
-            read_bit(code, data, ds, y, x);
+        if reserved(data, y, x) == 0 {
+            cells.push((x, y));
         }
-        if reserved_cell(data.version, y, x - 1) == 0 {
-            read_bit(code, data, ds, y, x - 1);
+        if reserved(data, y, x - 1) == 0 {
+            cells.push((x - 1, y));
         }
         y += dir;
-        if y < 0 || y >= code.size {
+        if y < 0 || y >= height {
             dir = -dir;
             x -= 2;
             y += dir
         }
     }
+
+    cells
 }
 
-fn codestream_ecc(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
-    let ver = &VERSION_DB[data.version];
-    let sb_ecc = &ver.ecc[data.ecc_level as usize];
+fn read_data(code: &Code, data: &mut Data, ds: &mut Datastream) {
+    let height = if data.kind == CodeKind::Rmqr {
+        code.height
+    } else {
+        code.width
+    };
+    for (x, y) in data_cells(data, code.width, height) {
+        read_bit(code, data, ds, y, x);
+    }
+}
 
-    let lb_count = (ver.data_bytes - sb_ecc.bs * sb_ecc.ns) / (sb_ecc.bs + 1);
+fn codestream_ecc(
+    data_bytes: i32,
+    sb_ecc: &RsParams,
+    ds: &mut Datastream,
+) -> Result<(), DecodeError> {
+    let lb_count = (data_bytes - sb_ecc.bs * sb_ecc.ns) / (sb_ecc.bs + 1);
     let bc = lb_count + sb_ecc.ns;
     let ecc_offset = sb_ecc.dw * bc + lb_count;
     let mut dst_offset = 0;
@@ -469,18 +778,40 @@ fn codestream_ecc(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeErro
     lb_ecc.dw += 1;
     lb_ecc.bs += 1;
 
+    let mut erasures = Vec::new();
     for i in 0..bc {
         let dst = &mut ds.data[dst_offset as usize..];
         let ecc = if i < sb_ecc.ns { sb_ecc } else { &lb_ecc };
-        let num_ec = ecc.bs - ecc.dw;
-        for j in 0..ecc.dw {
-            dst[j as usize] = ds.raw[(j * bc + i) as usize];
-        }
-        for j in 0..num_ec {
-            dst[(ecc.dw + j) as usize] = ds.raw[(ecc_offset + j * bc + i) as usize];
+        let offset = |j: i32| {
+            if j >= ecc.dw {
+                ecc_offset + (j - ecc.dw) * bc + i
+            } else if j < sb_ecc.dw {
+                j * bc + i
+            } else {
+                /* The last data codewords of the large blocks come after
+                 * the codewords shared by all blocks.
+                 */
+                j * bc + i - sb_ecc.ns
+            }
+        };
+
+        erasures.clear();
+        for j in 0..ecc.bs {
+            let k = offset(j) as usize;
+            dst[j as usize] = ds.raw[k];
+            if ds.erased[k] != 0 {
+                erasures.push(j as usize);
+            }
         }
 
-        correct_block(dst, ecc)?;
+        let mut block = correct_block(dst, ecc, &erasures)?;
+        for j in 0..ecc.bs {
+            ds.raw[offset(j) as usize] = dst[j as usize];
+        }
+        if ds.record_bits {
+            block.bits = (0..ecc.bs).map(|j| (offset(j) * 8, 8)).collect();
+        }
+        ds.blocks.push(block);
         dst_offset += ecc.dw;
     }
 
@@ -488,6 +819,112 @@ fn codestream_ecc(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeErro
     Ok(())
 }
 
+/// The number of ECC codewords which only protect against misdecoding,
+/// and can't be used for correction. These are only found in the
+/// smallest codes, which have a single block.
+pub(crate) fn misdecode_protection(data: &Data) -> usize {
+    match (data.kind, data.version, data.ecc_level) {
+        (CodeKind::Qr, 1, EccLevel::L) => 3,
+        (CodeKind::Qr, 1, EccLevel::M) => 2,
+        (CodeKind::Qr, 1, _) => 1,
+        (CodeKind::Qr, 2, EccLevel::L) => 2,
+        (CodeKind::Qr, 3, EccLevel::L) => 1,
+        (CodeKind::MicroQr, 1, _) => 2,
+        (CodeKind::MicroQr, 2, EccLevel::L) => 3,
+        (CodeKind::MicroQr, 2, _) => 2,
+        (CodeKind::MicroQr, 3, EccLevel::L) => 2,
+        (CodeKind::MicroQr, 4, EccLevel::L) => 2,
+        _ => 0,
+    }
+}
+
+/// Read a byte starting at any bit of the raw codestream, or of its
+/// erasures.
+fn raw_byte(raw: &[u8], bit: i32) -> u8 {
+    let hi = raw[(bit >> 3) as usize] as u16;
+    let lo = raw[(bit >> 3) as usize + 1] as u16;
+    ((hi << 8 | lo) << (bit & 7) >> 8) as u8
+}
+
+/// Write the top `len` bits of a byte starting at any bit of the raw
+/// codestream.
+fn set_raw_bits(raw: &mut [u8], bit: i32, value: u8, len: i32) {
+    for i in 0..len {
+        let pos = bit + i;
+        let mask = 0x80 >> (pos & 7);
+        if value & 0x80 >> i != 0 {
+            raw[(pos >> 3) as usize] |= mask;
+        } else {
+            raw[(pos >> 3) as usize] &= !mask;
+        }
+    }
+}
+
+/// Micro QR-codes hold a single block. In M1 and M3, the last data
+/// codeword is 4 bits long, and is followed directly by the ECC
+/// codewords. It is padded with zero bits for error correction.
+fn micro_codestream_ecc(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
+    let info = MICRO_SYMBOL_DB
+        .iter()
+        .find(|info| info.version == data.version && info.ecc_level == data.ecc_level)
+        .ok_or(DecodeError::InvalidVersion)?;
+    let ecc = &info.ecc;
+
+    let mut erasures = Vec::new();
+    let mut erased = [0; 64];
+    for i in 0..ecc.dw {
+        ds.data[i as usize] = raw_byte(&ds.raw, i * 8);
+        erased[i as usize] = raw_byte(&ds.erased, i * 8);
+    }
+    if info.data_bits & 7 != 0 {
+        ds.data[(ecc.dw - 1) as usize] &= 0xf0;
+        erased[(ecc.dw - 1) as usize] &= 0xf0;
+    }
+    for i in 0..ecc.bs - ecc.dw {
+        ds.data[(ecc.dw + i) as usize] = raw_byte(&ds.raw, info.data_bits + i * 8);
+        erased[(ecc.dw + i) as usize] = raw_byte(&ds.erased, info.data_bits + i * 8);
+    }
+    let bits = |i: i32| {
+        if i < ecc.dw {
+            (i * 8, (info.data_bits - i * 8).min(8))
+        } else {
+            (info.data_bits + (i - ecc.dw) * 8, 8)
+        }
+    };
+    for (i, e) in erased[..ecc.bs as usize].iter().enumerate() {
+        if *e != 0 {
+            erasures.push(i);
+        }
+    }
+
+    /* M1 only has enough ECC codewords to detect errors */
+    let block = &mut ds.data[..ecc.bs as usize];
+    let mut correction = if data.version == 1 {
+        let mut s = [0; 64];
+        if block_syndromes(block, ecc.bs, (ecc.bs - ecc.dw) as usize, &mut s) != 0 {
+            return Err(DecodeError::DataEcc);
+        }
+        BlockCorrection {
+            codewords: ecc.bs as usize,
+            ecc_codewords: (ecc.bs - ecc.dw) as usize,
+            ..Default::default()
+        }
+    } else {
+        correct_block(block, ecc, &erasures)?
+    };
+    for i in 0..ecc.bs {
+        let (bit, len) = bits(i);
+        set_raw_bits(&mut ds.raw, bit, ds.data[i as usize], len);
+    }
+    if ds.record_bits {
+        correction.bits = (0..ecc.bs).map(bits).collect();
+    }
+    ds.blocks.push(correction);
+
+    ds.data_bits = info.data_bits;
+    Ok(())
+}
+
 #[inline]
 fn bits_remaining(ds: &Datastream) -> i32 {
     ds.data_bits - ds.ptr
@@ -509,6 +946,34 @@ fn take_bits(ds: &mut Datastream, mut len: i32) -> i32 {
     ret
 }
 
+/// rMQR codes have their own character count lengths for each version.
+fn rmqr_count_bits(data: &Data, mode: usize) -> i32 {
+    RMQR_VERSION_DB[data.version - 1].count_bits[mode]
+}
+
+/// The length of the character count field of a segment in one of the
+/// numeric, alphanumeric, byte and Kanji modes. It depends on the kind
+/// and version of the code.
+pub(crate) fn count_bits(data: &Data, mode: DataType) -> i32 {
+    static QR_COUNT_BITS: [[i32; 3]; 4] = [[10, 12, 14], [9, 11, 13], [8, 16, 16], [8, 10, 12]];
+
+    let (index, micro_extra) = match mode {
+        DataType::Numeric => (0, 2),
+        DataType::Alpha => (1, 1),
+        DataType::Byte => (2, 1),
+        DataType::Kanji => (3, 0),
+        _ => return 0,
+    };
+
+    match data.kind {
+        CodeKind::Rmqr => rmqr_count_bits(data, index),
+        CodeKind::MicroQr => data.version as i32 + micro_extra,
+        CodeKind::Qr if data.version < 10 => QR_COUNT_BITS[index][0],
+        CodeKind::Qr if data.version < 27 => QR_COUNT_BITS[index][1],
+        CodeKind::Qr => QR_COUNT_BITS[index][2],
+    }
+}
+
 fn numeric_tuple(data: &mut Data, ds: &mut Datastream, bits: i32, digits: usize) -> i32 {
     if bits_remaining(ds) < bits {
         return -1;
@@ -525,14 +990,10 @@ fn numeric_tuple(data: &mut Data, ds: &mut Datastream, bits: i32, digits: usize)
     0
 }
 
-fn decode_numeric(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
-    let mut bits: i32 = 14;
-    if data.version < 10 {
-        bits = 10;
-    } else if data.version < 27 {
-        bits = 12;
-    }
+fn decode_numeric(data: &mut Data, ds: &mut Datastream) -> Result<usize, DecodeError> {
+    let bits = count_bits(data, DataType::Numeric);
     let mut count = take_bits(ds, bits);
+    let total = count as usize;
     if data.payload.len() + count as usize + 1 > 8896 {
         return Err(DecodeError::DataOverflow);
     }
@@ -553,7 +1014,7 @@ fn decode_numeric(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeErro
         return Err(DecodeError::DataUnderflow);
     }
 
-    Ok(())
+    Ok(total)
 }
 
 fn alpha_tuple(data: &mut Data, ds: &mut Datastream, bits: i32, digits: usize) -> i32 {
@@ -574,17 +1035,14 @@ fn alpha_tuple(data: &mut Data, ds: &mut Datastream, bits: i32, digits: usize) -
     0
 }
 
-fn decode_alpha(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
-    let mut bits: i32 = 13;
-    if data.version < 10 {
-        bits = 9
-    } else if data.version < 27 {
-        bits = 11
-    }
+fn decode_alpha(data: &mut Data, ds: &mut Datastream) -> Result<usize, DecodeError> {
+    let bits = count_bits(data, DataType::Alpha);
     let mut count = take_bits(ds, bits);
+    let total = count as usize;
     if data.payload.len() + count as usize + 1 > 8896 {
         return Err(DecodeError::DataOverflow);
     }
+    let start = data.payload.len();
     while count >= 2 {
         if alpha_tuple(data, ds, 11, 2) < 0 {
             return Err(DecodeError::DataUnderflow);
@@ -595,12 +1053,39 @@ fn decode_alpha(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError>
         return Err(DecodeError::DataUnderflow);
     }
 
-    Ok(())
+    if data.fnc1.is_some() {
+        fnc1_translate(&mut data.payload, start);
+    }
+
+    Ok(total)
 }
 
-fn decode_byte(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
-    let bits = if data.version < 10 { 8 } else { 16 };
+/// In FNC1 mode, a single `%` in alphanumeric data stands for the GS
+/// separator and `%%` for a literal `%`.
+fn fnc1_translate(payload: &mut Vec<u8>, start: usize) {
+    let mut out = start;
+    let mut i = start;
+    while i < payload.len() {
+        if payload[i] == b'%' {
+            if payload.get(i + 1) == Some(&b'%') {
+                payload[out] = b'%';
+                i += 1;
+            } else {
+                payload[out] = GS;
+            }
+        } else {
+            payload[out] = payload[i];
+        }
+        out += 1;
+        i += 1;
+    }
+    payload.truncate(out);
+}
+
+fn decode_byte(data: &mut Data, ds: &mut Datastream) -> Result<usize, DecodeError> {
+    let bits = count_bits(data, DataType::Byte);
     let count = take_bits(ds, bits);
+    let total = count as usize;
     if data.payload.len() + count as usize + 1 > 8896 {
         return Err(DecodeError::DataOverflow);
     }
@@ -612,18 +1097,14 @@ fn decode_byte(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError>
         data.payload.push(take_bits(ds, 8) as u8);
     }
 
-    Ok(())
+    Ok(total)
 }
 
-fn decode_kanji(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
-    let mut bits = 12;
-    if data.version < 10 {
-        bits = 8;
-    } else if data.version < 27 {
-        bits = 10;
-    }
+fn decode_kanji(data: &mut Data, ds: &mut Datastream) -> Result<usize, DecodeError> {
+    let bits = count_bits(data, DataType::Kanji);
 
     let count = take_bits(ds, bits);
+    let total = count as usize;
     if data.payload.len() + count as usize * 2 + 1 > 8896 {
         return Err(DecodeError::DataOverflow);
     }
@@ -648,52 +1129,180 @@ fn decode_kanji(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError>
         data.payload.push((sjw as i32 & 0xff) as u8);
     }
 
-    Ok(())
+    Ok(total)
 }
 
 fn decode_eci(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
     if bits_remaining(ds) < 8 {
         return Err(DecodeError::DataUnderflow);
     }
-    data.eci = Eci::from_u32(take_bits(ds, 8) as u32);
-    if data.eci.and_then(|e| e.to_u32()).unwrap_or_default() & 0xc0_u32 == 0x80_u32 {
+
+    /* The designator is 1, 2 or 3 bytes long, as indicated by the
+     * leading bits of the first byte.
+     */
+    let mut eci = take_bits(ds, 8) as u32;
+    if eci & 0xc0 == 0x80 {
         if bits_remaining(ds) < 8 {
             return Err(DecodeError::DataUnderflow);
         }
-        data.eci = Eci::from_u32(
-            data.eci.and_then(|e| e.to_u32()).unwrap_or_default() << 8 | take_bits(ds, 8) as u32,
-        );
-    } else if data.eci.and_then(|e| e.to_u32()).unwrap_or_default() & 0xe0_u32 == 0xc0_u32 {
+        eci = (eci & 0x3f) << 8 | take_bits(ds, 8) as u32;
+    } else if eci & 0xe0 == 0xc0 {
         if bits_remaining(ds) < 16 {
             return Err(DecodeError::DataUnderflow);
         }
-        data.eci = Eci::from_u32(
-            data.eci.and_then(|e| e.to_u32()).unwrap_or_default() << 16 | take_bits(ds, 16) as u32,
-        );
+        eci = (eci & 0x1f) << 16 | take_bits(ds, 16) as u32;
     }
 
+    data.eci = Eci::from_u32(eci);
+    data.eci_assignment = Some(eci);
+
+    Ok(())
+}
+
+fn decode_structured_append(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
+    if bits_remaining(ds) < 16 {
+        return Err(DecodeError::DataUnderflow);
+    }
+
+    let index = take_bits(ds, 4) as u8;
+    let total = take_bits(ds, 4) as u8 + 1;
+    let parity = take_bits(ds, 8) as u8;
+    data.structured_append = Some(StructuredAppend {
+        index,
+        total,
+        parity,
+    });
+
+    Ok(())
+}
+
+fn decode_fnc1_second(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
+    if bits_remaining(ds) < 8 {
+        return Err(DecodeError::DataUnderflow);
+    }
+
+    data.fnc1 = Some(Fnc1::Second(take_bits(ds, 8) as u8));
+
     Ok(())
 }
 
 fn decode_payload(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
     while bits_remaining(ds) >= 4 {
-        let type_0 = DataType::from_i32(take_bits(ds, 4));
-        match type_0 {
-            Some(DataType::Numeric) => decode_numeric(data, ds)?,
-            Some(DataType::Alpha) => decode_alpha(data, ds)?,
-            Some(DataType::Byte) => decode_byte(data, ds)?,
-            Some(DataType::Kanji) => decode_kanji(data, ds)?,
-            Some(DataType::Eci) => decode_eci(data, ds)?,
-            _ => {
-                break;
-            }
+        let type_0 = match DataType::from_i32(take_bits(ds, 4)) {
+            Some(type_0) => type_0,
+            None => break,
+        };
+        let start = data.payload.len();
+        let count = decode_segment(data, ds, type_0)?;
+
+        record_segment(data, Some(type_0), start, count);
+    }
+
+    Ok(())
+}
+
+/// Decode the segment following a mode indicator, returning the
+/// character count for data segments.
+fn decode_segment(
+    data: &mut Data,
+    ds: &mut Datastream,
+    type_0: DataType,
+) -> Result<Option<usize>, DecodeError> {
+    let count = match type_0 {
+        DataType::Numeric => Some(decode_numeric(data, ds)?),
+        DataType::Alpha => Some(decode_alpha(data, ds)?),
+        DataType::Byte => Some(decode_byte(data, ds)?),
+        DataType::Kanji => Some(decode_kanji(data, ds)?),
+        DataType::Eci => {
+            decode_eci(data, ds)?;
+            None
+        }
+        DataType::StructuredAppend => {
+            decode_structured_append(data, ds)?;
+            None
+        }
+        DataType::Fnc1First => {
+            data.fnc1 = Some(Fnc1::First);
+            None
         }
+        DataType::Fnc1Second => {
+            decode_fnc1_second(data, ds)?;
+            None
+        }
+    };
+
+    Ok(count)
+}
 
-        let t = type_0.map(|t| t as i32).unwrap_or_default();
-        let d = data.data_type.map(|t| t as i32).unwrap_or_default();
-        if t & (t - 1) == 0 && t > d {
-            data.data_type = type_0;
+fn record_segment(data: &mut Data, type_0: Option<DataType>, start: usize, count: Option<usize>) {
+    if let (Some(mode), Some(count)) = (type_0, count) {
+        data.segments.push(Segment {
+            mode,
+            eci: data.eci,
+            eci_assignment: data.eci_assignment,
+            count,
+            data: data.payload[start..].to_vec(),
+        });
+    }
+
+    let t = type_0.map(|t| t as i32).unwrap_or_default();
+    let d = data.data_type.map(|t| t as i32).unwrap_or_default();
+    if t & (t - 1) == 0 && t > d {
+        data.data_type = type_0;
+    }
+}
+
+/// Micro QR-codes only have the four data modes, with mode indicators
+/// and terminators that get longer with the version.
+fn decode_micro_payload(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
+    let mode_bits = data.version as i32 - 1;
+    let terminator_bits = data.version as i32 * 2 + 1;
+
+    while bits_remaining(ds) >= terminator_bits {
+        let ptr = ds.ptr;
+        if take_bits(ds, terminator_bits) == 0 {
+            break;
         }
+        ds.ptr = ptr;
+
+        let type_0 = match take_bits(ds, mode_bits) {
+            0 => DataType::Numeric,
+            1 => DataType::Alpha,
+            2 => DataType::Byte,
+            3 => DataType::Kanji,
+            _ => break,
+        };
+        let start = data.payload.len();
+        let count = match type_0 {
+            DataType::Numeric => decode_numeric(data, ds)?,
+            DataType::Alpha => decode_alpha(data, ds)?,
+            DataType::Byte => decode_byte(data, ds)?,
+            _ => decode_kanji(data, ds)?,
+        };
+
+        record_segment(data, Some(type_0), start, Some(count));
+    }
+
+    Ok(())
+}
+
+/// rMQR codes have 3-bit mode indicators, and no structured append.
+fn decode_rmqr_payload(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeError> {
+    while bits_remaining(ds) >= 3 {
+        let type_0 = match take_bits(ds, 3) {
+            1 => DataType::Numeric,
+            2 => DataType::Alpha,
+            3 => DataType::Byte,
+            4 => DataType::Kanji,
+            5 => DataType::Fnc1First,
+            6 => DataType::Fnc1Second,
+            7 => DataType::Eci,
+            _ => break,
+        };
+        let start = data.payload.len();
+        let count = decode_segment(data, ds, type_0)?;
+
+        record_segment(data, Some(type_0), start, count);
     }
 
     Ok(())
@@ -701,20 +1310,114 @@ fn decode_payload(data: &mut Data, ds: &mut Datastream) -> Result<(), DecodeErro
 
 impl Code {
     /// Decode a QR-code, returning the payload data.
+    ///
+    /// If the code can not be read as it is, decoding is retried on the
+    /// transposed grid, as seen when a code is viewed from behind or in
+    /// a mirror. `Data::mirrored` is set if that attempt succeeded.
+    /// Mirrored rMQR codes are not supported.
     pub fn decode(&self) -> Result<Data, DecodeError> {
+        self.decode_stream(false).map(|(data, _)| data)
+    }
+
+    /// Decode a QR-code, also returning the codewords it was decoded
+    /// from. If `record_bits` is set, the blocks also record where their
+    /// codewords lie in the codestream. If the code was mirrored, the
+    /// codestream is that of the transposed grid.
+    pub(crate) fn decode_stream(
+        &self,
+        record_bits: bool,
+    ) -> Result<(Data, Datastream), DecodeError> {
+        match self.decode_grid(record_bits) {
+            Err(err @ DecodeError::FormatEcc) | Err(err @ DecodeError::DataEcc)
+                if self.kind != CodeKind::Rmqr =>
+            {
+                self.transpose()
+                    .decode_grid(record_bits)
+                    .map(|(data, ds)| {
+                        (
+                            Data {
+                                mirrored: true,
+                                ..data
+                            },
+                            ds,
+                        )
+                    })
+                    .map_err(|_| err)
+            }
+            res => res,
+        }
+    }
+
+    /// Mirror the code along its main diagonal.
+    fn transpose(&self) -> Code {
+        let mut code = Code {
+            corners: [
+                self.corners[0],
+                self.corners[3],
+                self.corners[2],
+                self.corners[1],
+            ],
+            cell_bitmap: [0; 3917],
+            erasure_bitmap: [0; 3917],
+            ..*self
+        };
+
+        for y in 0..self.width {
+            for x in 0..self.width {
+                let p = x * self.width + y;
+                if grid_bit(self, x, y) != 0 {
+                    code.cell_bitmap[(p >> 3) as usize] |= 1 << (p & 7);
+                }
+                if grid_erased(self, x, y) {
+                    code.erasure_bitmap[(p >> 3) as usize] |= 1 << (p & 7);
+                }
+            }
+        }
+
+        code
+    }
+
+    fn decode_grid(&self, record_bits: bool) -> Result<(Data, Datastream), DecodeError> {
         let mut ds: Datastream = Datastream {
             raw: [0; 8896],
+            erased: [0; 8896],
+            record_bits,
+            blocks: Vec::new(),
             data_bits: 0,
             ptr: 0,
             data: [0; 8896],
         };
 
-        if (self.size - 17) % 4 != 0 {
+        let mut data = match self.kind {
+            CodeKind::MicroQr => self.decode_micro_grid(&mut ds)?,
+            CodeKind::Rmqr => self.decode_rmqr_grid(&mut ds)?,
+            CodeKind::Qr => self.decode_qr_grid(&mut ds)?,
+        };
+        data.blocks = ds
+            .blocks
+            .iter()
+            .map(|block| BlockStats {
+                codewords: block.codewords,
+                ecc_codewords: block.ecc_codewords,
+                errors: block.errors.len(),
+                erasures: block.erasures,
+                capacity: block
+                    .ecc_codewords
+                    .saturating_sub(misdecode_protection(&data))
+                    / 2,
+            })
+            .collect();
+
+        Ok((data, ds))
+    }
+
+    fn decode_qr_grid(&self, ds: &mut Datastream) -> Result<Data, DecodeError> {
+        if (self.width - 17) % 4 != 0 {
             return Err(DecodeError::InvalidGridSize);
         }
 
         let mut data = Data {
-            version: usize::try_from((self.size - 17) / 4)
+            version: usize::try_from((self.width - 17) / 4)
                 .map_err(|_| DecodeError::InvalidVersion)?,
             ..Default::default()
         };
@@ -730,9 +1433,64 @@ impl Code {
         }
         res?;
 
-        read_data(self, &mut data, &mut ds);
-        codestream_ecc(&mut data, &mut ds)?;
-        decode_payload(&mut data, &mut ds)?;
+        /* If the version information can be read, it must agree with the
+         * grid size.
+         */
+        if data.version >= VERSION_INFO_MIN {
+            let versions = [read_version(self, 0), read_version(self, 1)];
+            if versions.iter().any(Option::is_some) && !versions.contains(&Some(data.version)) {
+                return Err(DecodeError::InvalidVersion);
+            }
+        }
+
+        read_data(self, &mut data, ds);
+        let ver = &VERSION_DB[data.version];
+        codestream_ecc(ver.data_bytes, &ver.ecc[data.ecc_level as usize], ds)?;
+        decode_payload(&mut data, ds)?;
+
+        Ok(data)
+    }
+
+    fn decode_micro_grid(&self, ds: &mut Datastream) -> Result<Data, DecodeError> {
+        if self.width < 11 || self.width > 17 || self.width % 2 == 0 {
+            return Err(DecodeError::InvalidGridSize);
+        }
+
+        let mut data = Data {
+            kind: CodeKind::MicroQr,
+            ..Default::default()
+        };
+
+        read_micro_format(self, &mut data)?;
+        read_data(self, &mut data, ds);
+        micro_codestream_ecc(&mut data, ds)?;
+        decode_micro_payload(&mut data, ds)?;
+
+        Ok(data)
+    }
+
+    fn decode_rmqr_grid(&self, ds: &mut Datastream) -> Result<Data, DecodeError> {
+        if rmqr_version(self.height, self.width).is_none() {
+            return Err(DecodeError::InvalidGridSize);
+        }
+
+        let mut data = Data {
+            kind: CodeKind::Rmqr,
+            ..Default::default()
+        };
+
+        /* Read format information -- try both copies */
+        let mut res = read_rmqr_format(self, &mut data, 0);
+        if res.is_err() {
+            res = read_rmqr_format(self, &mut data, 1);
+        }
+        res?;
+
+        read_data(self, &mut data, ds);
+        let info = &RMQR_VERSION_DB[data.version - 1];
+        let ecc = &info.ecc[(data.ecc_level == EccLevel::H) as usize];
+        codestream_ecc(info.data_bytes, ecc, ds)?;
+        decode_rmqr_payload(&mut data, ds)?;
 
         Ok(data)
     }
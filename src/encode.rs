@@ -0,0 +1,913 @@
+use crate::decode::{
+    count_bits, data_cells, data_mask, mask_bit, rmqr_format_cell, GF256_EXP, GF256_LOG,
+    RMQR_FORMAT_MASK,
+};
+use crate::error::EncodeError;
+use crate::quirc::*;
+use crate::version_db::*;
+
+static ALPHA_MAP: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+/// A string of bits, filled from the most significant bit of each byte.
+#[derive(Default)]
+struct BitBuffer {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitBuffer {
+    fn push(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if self.len & 7 == 0 {
+                self.bytes.push(0);
+            }
+            if value >> i & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+impl Segment {
+    /// Construct a segment holding the given data in one mode. Kanji
+    /// data is given as Shift-JIS.
+    pub fn new(mode: DataType, data: impl Into<Vec<u8>>) -> Self {
+        let data = data.into();
+        let count = if mode == DataType::Kanji {
+            data.len() / 2
+        } else {
+            data.len()
+        };
+
+        Self {
+            mode,
+            eci: None,
+            eci_assignment: None,
+            count,
+            data,
+        }
+    }
+}
+
+/// The length of the character count field of a segment in a version.
+fn qr_count_bits(mode: DataType, version: usize) -> usize {
+    let data = Data {
+        version,
+        ..Default::default()
+    };
+
+    count_bits(&data, mode) as usize
+}
+
+/// The number of bits taken by `len` characters in a mode, leaving out
+/// the header. For Kanji, `len` is the number of bytes.
+pub(crate) fn data_bits(mode: DataType, len: usize) -> usize {
+    match mode {
+        DataType::Numeric => len / 3 * 10 + [0, 4, 7][len % 3],
+        DataType::Alpha => len / 2 * 11 + len % 2 * 6,
+        DataType::Kanji => len / 2 * 13,
+        _ => len * 8,
+    }
+}
+
+/// The 13 bit value of a Shift-JIS double-byte character, if it can be
+/// encoded in Kanji mode.
+fn kanji_value(hi: u8, lo: u8) -> Option<u32> {
+    if !(0x40..=0xfc).contains(&lo) || lo == 0x7f {
+        return None;
+    }
+    let c = (hi as u32) << 8 | lo as u32;
+    let c = match c {
+        0x8140..=0x9ffc => c - 0x8140,
+        0xe040..=0xebbf => c - 0xc140,
+        _ => return None,
+    };
+
+    Some((c >> 8) * 0xc0 + (c & 0xff))
+}
+
+fn check_segment(segment: &Segment) -> Result<(), EncodeError> {
+    let data = &segment.data;
+    let valid = match segment.mode {
+        DataType::Numeric => data.iter().all(u8::is_ascii_digit),
+        DataType::Alpha => data.iter().all(|c| ALPHA_MAP.contains(c)),
+        DataType::Byte => true,
+        DataType::Kanji => {
+            data.len() & 1 == 0
+                && data
+                    .chunks(2)
+                    .all(|pair| kanji_value(pair[0], pair[1]).is_some())
+        }
+        mode => return Err(EncodeError::UnsupportedMode(mode)),
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(EncodeError::InvalidData(segment.mode))
+    }
+}
+
+/// The ECI assignment of a segment, if it has one.
+fn segment_eci(segment: &Segment) -> Option<u32> {
+    segment
+        .eci_assignment
+        .or_else(|| segment.eci.map(|eci| eci as u32))
+}
+
+/// The length of an ECI designator.
+fn eci_bits(assignment: u32) -> Result<usize, EncodeError> {
+    match assignment {
+        0..=127 => Ok(8),
+        128..=16383 => Ok(16),
+        16384..=999_999 => Ok(24),
+        _ => Err(EncodeError::InvalidEci(assignment)),
+    }
+}
+
+/// The length of the mode indicators, and of the terminator, of a kind of
+/// symbol.
+fn indicator_bits(symbol: &Data) -> usize {
+    match symbol.kind {
+        CodeKind::Rmqr => 3,
+        _ => 4,
+    }
+}
+
+/// The mode indicator of a segment. rMQR codes number the modes in
+/// order.
+fn mode_indicator(symbol: &Data, mode: DataType) -> u32 {
+    match (symbol.kind, mode) {
+        (CodeKind::Rmqr, DataType::Numeric) => 1,
+        (CodeKind::Rmqr, DataType::Alpha) => 2,
+        (CodeKind::Rmqr, DataType::Byte) => 3,
+        (CodeKind::Rmqr, DataType::Kanji) => 4,
+        (_, mode) => mode as u32,
+    }
+}
+
+/// The number of bits the segments take in a symbol, whose kind and
+/// version are given by `symbol`, or `None` if a character count doesn't
+/// fit.
+fn segments_bits(segments: &[Segment], symbol: &Data) -> Result<Option<usize>, EncodeError> {
+    let header = indicator_bits(symbol);
+    let mut bits = 0;
+    let mut eci = None;
+    for segment in segments {
+        if let Some(assignment) = segment_eci(segment).filter(|a| eci != Some(*a)) {
+            bits += header + eci_bits(assignment)?;
+            eci = Some(assignment);
+        }
+
+        let count = if segment.mode == DataType::Kanji {
+            segment.data.len() / 2
+        } else {
+            segment.data.len()
+        };
+        let count_bits = count_bits(symbol, segment.mode) as usize;
+        if count >> count_bits != 0 {
+            return Ok(None);
+        }
+        bits += header + count_bits + data_bits(segment.mode, segment.data.len());
+    }
+
+    Ok(Some(bits))
+}
+
+fn push_segments(buf: &mut BitBuffer, segments: &[Segment], symbol: &Data) {
+    let header = indicator_bits(symbol);
+    let mut eci = None;
+    for segment in segments {
+        if let Some(assignment) = segment_eci(segment).filter(|a| eci != Some(*a)) {
+            buf.push(mode_indicator(symbol, DataType::Eci), header);
+            match assignment {
+                0..=127 => buf.push(assignment, 8),
+                128..=16383 => buf.push(0x8000 | assignment, 16),
+                _ => buf.push(0xc0_0000 | assignment, 24),
+            }
+            eci = Some(assignment);
+        }
+
+        let data = &segment.data;
+        let count = if segment.mode == DataType::Kanji {
+            data.len() / 2
+        } else {
+            data.len()
+        };
+        buf.push(mode_indicator(symbol, segment.mode), header);
+        buf.push(count as u32, count_bits(symbol, segment.mode) as usize);
+
+        match segment.mode {
+            DataType::Numeric => {
+                for digits in data.chunks(3) {
+                    let value = digits
+                        .iter()
+                        .fold(0, |value, d| value * 10 + (d - b'0') as u32);
+                    buf.push(value, [0, 4, 7, 10][digits.len()]);
+                }
+            }
+            DataType::Alpha => {
+                for pair in data.chunks(2) {
+                    let value = |c: &u8| ALPHA_MAP.iter().position(|a| a == c).unwrap() as u32;
+                    match pair {
+                        [a, b] => buf.push(value(a) * 45 + value(b), 11),
+                        [a] => buf.push(value(a), 6),
+                        _ => unreachable!(),
+                    }
+                }
+            }
+            DataType::Kanji => {
+                for pair in data.chunks(2) {
+                    buf.push(kanji_value(pair[0], pair[1]).unwrap(), 13);
+                }
+            }
+            _ => {
+                for byte in data {
+                    buf.push(*byte as u32, 8);
+                }
+            }
+        }
+    }
+}
+
+/// A character of the data to be encoded.
+struct Unit {
+    /// The bytes of the character in byte mode.
+    bytes: Vec<u8>,
+    /// The Shift-JIS character, if it can be encoded in Kanji mode.
+    kanji: Option<[u8; 2]>,
+    /// Set if the character can only be encoded in byte mode after an
+    /// ECI designator for UTF-8.
+    utf8: bool,
+}
+
+impl Unit {
+    /// The cost of the character in a mode, in sixths of a bit, or
+    /// `None` if it can't be encoded in it.
+    fn cost(&self, mode: DataType, utf8: bool) -> Option<usize> {
+        let ascii = match self.bytes[..] {
+            [c] if c.is_ascii() => Some(c),
+            _ => None,
+        };
+
+        match mode {
+            DataType::Numeric => ascii.filter(u8::is_ascii_digit).map(|_| 20),
+            DataType::Alpha => ascii.filter(|c| ALPHA_MAP.contains(c)).map(|_| 33),
+            DataType::Byte if utf8 || !self.utf8 => Some(self.bytes.len() * 48),
+            DataType::Kanji => self.kanji.map(|_| 78),
+            _ => None,
+        }
+    }
+}
+
+static MODES: [DataType; 4] = [
+    DataType::Numeric,
+    DataType::Alpha,
+    DataType::Byte,
+    DataType::Kanji,
+];
+
+/// Split characters into the segments which encode them in the fewest
+/// bits in a version.
+///
+/// This finds the cheapest way to encode each prefix of the data, for
+/// each mode the last character can be in and whether an ECI designator
+/// for UTF-8 came before it.
+fn optimal_segments(units: &[Unit], version: usize) -> Vec<Segment> {
+    /* Costs are counted in sixths of a bit, which is exact for numeric
+     * and alphanumeric characters once a segment is rounded up to whole
+     * bits.
+     */
+    let ceil = |cost: usize| cost.div_ceil(6) * 6;
+    let header = |state: usize| (4 + qr_count_bits(MODES[state % 4], version)) * 6;
+    const ECI_COST: usize = 12 * 6;
+
+    let mut costs: [Option<usize>; 8] = [None; 8];
+    let mut from = vec![[0; 8]; units.len()];
+    for (i, unit) in units.iter().enumerate() {
+        let mut next = [None; 8];
+        for state in 0..8 {
+            let utf8 = state >= 4;
+            let cost = match unit.cost(MODES[state % 4], utf8) {
+                Some(cost) => cost,
+                None => continue,
+            };
+
+            /* Either continue the segment, or start a new one. The ECI
+             * stays in effect once it has been given.
+             */
+            let mut best = costs[state].map(|c| (c, state));
+            for (prev, prev_cost) in costs.iter().enumerate() {
+                let prev_utf8 = prev >= 4;
+                let prev_cost = match prev_cost {
+                    Some(c) if !prev_utf8 || utf8 => *c,
+                    _ => continue,
+                };
+                let eci = if utf8 && !prev_utf8 { ECI_COST } else { 0 };
+                let c = ceil(prev_cost) + eci + header(state);
+                if best.is_none_or(|(b, _)| c < b) {
+                    best = Some((c, prev));
+                }
+            }
+            if i == 0 {
+                best = Some((header(state) + utf8 as usize * ECI_COST, state));
+            }
+
+            if let Some((c, prev)) = best {
+                next[state] = Some(c + cost);
+                from[i][state] = prev;
+            }
+        }
+        costs = next;
+    }
+
+    let mut state = match (0..8).filter_map(|s| costs[s].map(|c| (ceil(c), s))).min() {
+        Some((_, state)) => state,
+        None => return Vec::new(),
+    };
+    let mut states = vec![0; units.len()];
+    for i in (0..units.len()).rev() {
+        states[i] = state;
+        state = from[i][state];
+    }
+
+    let mut segments: Vec<(usize, Vec<u8>)> = Vec::new();
+    for (unit, state) in units.iter().zip(states) {
+        let bytes = match unit.kanji {
+            Some(kanji) if MODES[state % 4] == DataType::Kanji => kanji.to_vec(),
+            _ => unit.bytes.clone(),
+        };
+        match segments.last_mut() {
+            Some((s, data)) if *s == state => data.extend(bytes),
+            _ => segments.push((state, bytes)),
+        }
+    }
+
+    segments
+        .into_iter()
+        .map(|(state, data)| {
+            let mut segment = Segment::new(MODES[state % 4], data);
+            if state >= 4 {
+                segment.eci = Some(Eci::Utf8);
+                segment.eci_assignment = Some(Eci::Utf8 as u32);
+            }
+            segment
+        })
+        .collect()
+}
+
+/// The Shift-JIS double-byte character for a character, if it has one
+/// which can be encoded in Kanji mode.
+#[cfg(feature = "text")]
+fn shift_jis(c: char) -> Option<[u8; 2]> {
+    let mut buf = [0; 4];
+    let (bytes, _, unmappable) = encoding_rs::SHIFT_JIS.encode(c.encode_utf8(&mut buf));
+    match bytes[..] {
+        [hi, lo] if !unmappable && kanji_value(hi, lo).is_some() => Some([hi, lo]),
+        _ => None,
+    }
+}
+
+/// Without the `text` feature, there is no Shift-JIS table, and text is
+/// never encoded in Kanji mode.
+#[cfg(not(feature = "text"))]
+fn shift_jis(_: char) -> Option<[u8; 2]> {
+    None
+}
+
+impl Segment {
+    /// Split data into the segments which encode it in the fewest bits
+    /// in a version. Pairs of bytes which form a Shift-JIS double-byte
+    /// character may be put in Kanji mode.
+    pub fn optimize(data: &[u8], version: usize) -> Vec<Segment> {
+        let mut units = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let kanji = data
+                .get(i + 1)
+                .filter(|lo| kanji_value(data[i], **lo).is_some())
+                .map(|lo| [data[i], *lo]);
+            let len = if kanji.is_some() { 2 } else { 1 };
+            units.push(Unit {
+                bytes: data[i..i + len].to_vec(),
+                kanji,
+                utf8: false,
+            });
+            i += len;
+        }
+
+        optimal_segments(&units, version)
+    }
+
+    /// Split text into the segments which encode it in the fewest bits
+    /// in a version. Characters which aren't ASCII are put in byte mode
+    /// as UTF-8, after an ECI designator, or with the `text` feature, in
+    /// Kanji mode if they are in Shift-JIS.
+    pub fn optimize_text(text: &str, version: usize) -> Vec<Segment> {
+        let units: Vec<Unit> = text
+            .chars()
+            .map(|c| {
+                let mut buf = [0; 4];
+                Unit {
+                    bytes: c.encode_utf8(&mut buf).as_bytes().to_vec(),
+                    kanji: shift_jis(c),
+                    utf8: !c.is_ascii(),
+                }
+            })
+            .collect();
+
+        optimal_segments(&units, version)
+    }
+}
+
+/// The total number of codewords of a symbol, and the blocks they are
+/// split into at an error correction level.
+fn block_params(symbol: &Data, ecc_level: EccLevel) -> (i32, &'static RsParams) {
+    match symbol.kind {
+        CodeKind::Rmqr => {
+            let info = &RMQR_VERSION_DB[symbol.version - 1];
+            (
+                info.data_bytes,
+                &info.ecc[(ecc_level == EccLevel::H) as usize],
+            )
+        }
+        _ => {
+            let ver = &VERSION_DB[symbol.version];
+            (ver.data_bytes, &ver.ecc[ecc_level as usize])
+        }
+    }
+}
+
+/// The number of data codewords of a symbol, and the number of small
+/// and large blocks they are split into.
+fn data_layout(symbol: &Data, ecc_level: EccLevel) -> (usize, usize, usize) {
+    let (total, sb_ecc) = block_params(symbol, ecc_level);
+    let lb_count = (total - sb_ecc.bs * sb_ecc.ns) / (sb_ecc.bs + 1);
+    let data_bytes = sb_ecc.dw * sb_ecc.ns + (sb_ecc.dw + 1) * lb_count;
+
+    (data_bytes as usize, sb_ecc.ns as usize, lb_count as usize)
+}
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    GF256_EXP[(GF256_LOG[a as usize] as usize + GF256_LOG[b as usize] as usize) % 255]
+}
+
+/// Compute the Reed-Solomon ECC codewords of a block. The generator
+/// polynomial has the roots alpha^0 .. alpha^(npar - 1).
+fn rs_parity(data: &[u8], npar: usize) -> Vec<u8> {
+    let mut generator = vec![1u8];
+    for root in &GF256_EXP[..npar] {
+        let mut next = vec![0; generator.len() + 1];
+        for (j, c) in generator.iter().enumerate() {
+            next[j] ^= *c;
+            next[j + 1] ^= gf_mul(*c, *root);
+        }
+        generator = next;
+    }
+
+    let mut rem = vec![0u8; npar];
+    for d in data {
+        let factor = d ^ rem[0];
+        rem.remove(0);
+        rem.push(0);
+        for (r, g) in rem.iter_mut().zip(&generator[1..]) {
+            *r ^= gf_mul(*g, factor);
+        }
+    }
+
+    rem
+}
+
+/// Split the data codewords into blocks, add the ECC codewords of each
+/// and interleave them.
+fn codewords(data: &[u8], symbol: &Data, ecc_level: EccLevel) -> Vec<u8> {
+    let (_, sb_ecc) = block_params(symbol, ecc_level);
+    let (_, ns, lb_count) = data_layout(symbol, ecc_level);
+    let dw = sb_ecc.dw as usize;
+    let npar = (sb_ecc.bs - sb_ecc.dw) as usize;
+
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    for i in 0..ns + lb_count {
+        let len = if i < ns { dw } else { dw + 1 };
+        blocks.push(&data[offset..offset + len]);
+        offset += len;
+    }
+    let parity: Vec<Vec<u8>> = blocks.iter().map(|b| rs_parity(b, npar)).collect();
+
+    let mut out = Vec::new();
+    for j in 0..=dw {
+        for block in &blocks {
+            if let Some(c) = block.get(j) {
+                out.push(*c);
+            }
+        }
+    }
+    for j in 0..npar {
+        for p in &parity {
+            out.push(p[j]);
+        }
+    }
+
+    out
+}
+
+/// Encode segments into the codewords of a symbol, with the terminator,
+/// padding up to the capacity, and error correction.
+fn codestream(segments: &[Segment], symbol: &Data, ecc_level: EccLevel) -> Vec<u8> {
+    let (data_bytes, _, _) = data_layout(symbol, ecc_level);
+    let mut buf = BitBuffer::default();
+    push_segments(&mut buf, segments, symbol);
+    buf.push(0, (data_bytes * 8 - buf.len).min(indicator_bits(symbol)));
+    buf.push(0, (8 - buf.len % 8) % 8);
+    for pad in [0xec, 0x11]
+        .iter()
+        .cycle()
+        .take(data_bytes - buf.bytes.len())
+    {
+        buf.push(*pad, 8);
+    }
+
+    codewords(&buf.bytes, symbol, ecc_level)
+}
+
+/// A matrix of cells under construction.
+#[derive(Clone)]
+struct Matrix {
+    width: i32,
+    dark: Vec<bool>,
+}
+
+impl Matrix {
+    fn new(width: i32, height: i32) -> Self {
+        Self {
+            width,
+            dark: vec![false; (width * height) as usize],
+        }
+    }
+
+    fn set(&mut self, x: i32, y: i32, dark: bool) {
+        self.dark[(y * self.width + x) as usize] = dark;
+    }
+
+    fn get(&self, x: i32, y: i32) -> bool {
+        self.dark[(y * self.width + x) as usize]
+    }
+
+    /// Place the codestream in the data cells, given in order, masked
+    /// with one of the QR-code mask patterns.
+    fn place_data(&mut self, cells: &[(i32, i32)], stream: &[u8], mask: i32) {
+        for (i, (x, y)) in cells.iter().enumerate() {
+            let bit = stream.get(i / 8).is_some_and(|c| c & 0x80 >> (i % 8) != 0);
+            self.set(*x, *y, bit != (mask_bit(mask, *y, *x) != 0));
+        }
+    }
+
+    fn into_code(self, kind: CodeKind) -> Code {
+        let mut code = Code {
+            width: self.width,
+            height: self.dark.len() as i32 / self.width,
+            kind,
+            ..Default::default()
+        };
+        for (i, dark) in self.dark.iter().enumerate() {
+            if *dark {
+                code.cell_bitmap[i >> 3] |= 1 << (i & 7);
+            }
+        }
+
+        code
+    }
+}
+
+/// Draw the finder, timing and alignment patterns, the dark module and
+/// the version information.
+fn function_patterns(matrix: &mut Matrix, version: usize) {
+    let size = matrix.width;
+    for (fx, fy) in [(0, 0), (size - 7, 0), (0, size - 7)] {
+        for j in 0..7 {
+            for i in 0..7 {
+                let ring = (i - 3_i32).abs().max((j - 3_i32).abs());
+                matrix.set(fx + i, fy + j, ring != 2);
+            }
+        }
+    }
+
+    for i in 8..size - 8 {
+        matrix.set(i, 6, i % 2 == 0);
+        matrix.set(6, i, i % 2 == 0);
+    }
+
+    let apat = &VERSION_DB[version].apat;
+    let count = apat.iter().take_while(|a| **a != 0).count();
+    for j in 0..count {
+        for i in 0..count {
+            /* Three of the positions are taken by finder patterns */
+            if matches!((i, j), (0, 0)) || (i == 0 && j == count - 1) || (i == count - 1 && j == 0)
+            {
+                continue;
+            }
+            for v in -2..=2_i32 {
+                for u in -2..=2_i32 {
+                    let ring = u.abs().max(v.abs());
+                    matrix.set(apat[i] + u, apat[j] + v, ring != 1);
+                }
+            }
+        }
+    }
+
+    matrix.set(8, size - 8, true);
+
+    if version >= VERSION_INFO_MIN {
+        let bits = version_info_bits(version);
+        for i in 0..18 {
+            let (a, b) = (size - 11 + i % 3, i / 3);
+            let dark = bits >> i & 1 != 0;
+            matrix.set(a, b, dark);
+            matrix.set(b, a, dark);
+        }
+    }
+}
+
+/// Draw both copies of the format information.
+fn format_info(matrix: &mut Matrix, ecc_level: EccLevel, mask: i32) {
+    let fdata = (ecc_level as u32) << 3 | mask as u32;
+    let mut rem = fdata << 10;
+    for i in (10..15).rev() {
+        if rem & (1 << i) != 0 {
+            rem ^= 0x537 << (i - 10);
+        }
+    }
+    let format = (fdata << 10 | rem) ^ 0x5412;
+
+    let size = matrix.width;
+    for (i, (x, y)) in FORMAT_CELLS.iter().enumerate() {
+        matrix.set(*x, *y, format >> i & 1 != 0);
+    }
+    for i in 0..7 {
+        matrix.set(8, size - 1 - i, format >> (14 - i) & 1 != 0);
+    }
+    for i in 0..8 {
+        matrix.set(size - 8 + i, 8, format >> (7 - i) & 1 != 0);
+    }
+}
+
+/// Draw the timing, alignment and finder patterns of an rMQR code, the
+/// later ones taking precedence where they overlap.
+fn rmqr_function_patterns(matrix: &mut Matrix, info: &RmqrVersionInfo) {
+    let (w, h) = (info.width, info.height);
+    for x in 0..w {
+        matrix.set(x, 0, x % 2 == 0);
+        matrix.set(x, h - 1, x % 2 == 0);
+    }
+    for y in 0..h {
+        matrix.set(0, y, y % 2 == 0);
+        matrix.set(w - 1, y, y % 2 == 0);
+    }
+
+    /* Alignment patterns at the top and bottom, joined by vertical
+     * timing patterns.
+     */
+    for &cx in info.apat.iter().take_while(|p| **p != 0) {
+        for y in 0..h {
+            matrix.set(cx, y, y % 2 == 0);
+        }
+        for cy in [1, h - 2] {
+            for v in -1..=1 {
+                for u in -1..=1 {
+                    matrix.set(cx + u, cy + v, (u, v) != (0, 0));
+                }
+            }
+        }
+    }
+
+    /* Finder pattern and its separator */
+    for y in 0..8.min(h) {
+        for x in 0..8 {
+            let ring = (x - 3_i32).abs().max((y - 3_i32).abs());
+            matrix.set(x, y, x < 7 && y < 7 && ring != 2);
+        }
+    }
+
+    /* Finder sub-pattern */
+    for v in 0..5 {
+        for u in 0..5 {
+            let ring = (u - 2_i32).abs().max((v - 2_i32).abs());
+            matrix.set(w - 5 + u, h - 5 + v, ring != 1);
+        }
+    }
+
+    /* Corner finder patterns */
+    matrix.set(w - 2, 0, true);
+    matrix.set(w - 1, 1, true);
+    matrix.set(w - 2, 1, false);
+    if h > 9 {
+        matrix.set(1, h - 1, true);
+        matrix.set(0, h - 2, true);
+        matrix.set(1, h - 2, false);
+    }
+}
+
+/// Draw both copies of the rMQR format information.
+fn rmqr_format_info(
+    matrix: &mut Matrix,
+    info: &RmqrVersionInfo,
+    version: usize,
+    ecc_level: EccLevel,
+) {
+    let fdata = (ecc_level == EccLevel::H) as usize * 0x20 + version - RMQR_VERSION_MIN;
+    for (which, mask) in RMQR_FORMAT_MASK.iter().enumerate() {
+        let bits = version_info_bits(fdata) ^ mask;
+        for n in 0..18 {
+            let (x, y) = rmqr_format_cell(info.width, info.height, which, n);
+            matrix.set(x, y, bits >> n & 1 != 0);
+        }
+    }
+}
+
+/// The penalty score of a masked symbol, by the rules of ISO/IEC 18004.
+fn penalty(matrix: &Matrix) -> i32 {
+    let size = matrix.width;
+    let mut score = 0;
+
+    for vertical in [false, true] {
+        let get = |a: i32, b: i32| {
+            if vertical {
+                matrix.get(b, a)
+            } else {
+                matrix.get(a, b)
+            }
+        };
+
+        for b in 0..size {
+            /* Runs of five or more cells of the same colour */
+            let mut run = 1;
+            for a in 1..size {
+                if get(a, b) == get(a - 1, b) {
+                    run += 1;
+                    if run == 5 {
+                        score += 3;
+                    } else if run > 5 {
+                        score += 1;
+                    }
+                } else {
+                    run = 1;
+                }
+            }
+
+            /* Patterns looking like a finder pattern */
+            for a in 0..=size - 11 {
+                let bits: Vec<bool> = (a..a + 11).map(|a| get(a, b)).collect();
+                let finder = [true, false, true, true, true, false, true];
+                let light = [false; 4];
+                if bits[..7] == finder && bits[7..] == light
+                    || bits[..4] == light && bits[4..] == finder
+                {
+                    score += 40;
+                }
+            }
+        }
+    }
+
+    /* Blocks of 2x2 cells of the same colour */
+    for y in 0..size - 1 {
+        for x in 0..size - 1 {
+            let c = matrix.get(x, y);
+            if matrix.get(x + 1, y) == c
+                && matrix.get(x, y + 1) == c
+                && matrix.get(x + 1, y + 1) == c
+            {
+                score += 3;
+            }
+        }
+    }
+
+    /* Balance of dark and light cells */
+    let dark = matrix.dark.iter().filter(|d| **d).count() as i32;
+    let percent = dark * 100 / (size * size);
+    score += (percent - 50).abs() / 5 * 10;
+
+    score
+}
+
+impl Code {
+    /// Encode data as a QR-code, in the smallest version which can hold
+    /// it at the given error correction level. The data is split into
+    /// segments by `Segment::optimize`.
+    pub fn encode(data: &[u8], ecc_level: EccLevel) -> Result<Code, EncodeError> {
+        Self::encode_with(ecc_level, |version| Segment::optimize(data, version))
+    }
+
+    /// Encode text as a QR-code, like `encode`. The text is split into
+    /// segments by `Segment::optimize_text`.
+    pub fn encode_text(text: &str, ecc_level: EccLevel) -> Result<Code, EncodeError> {
+        Self::encode_with(ecc_level, |version| Segment::optimize_text(text, version))
+    }
+
+    /// Encode a sequence of segments as a QR-code, in the smallest
+    /// version which can hold them at the given error correction level.
+    /// An ECI designator is put before each segment whose ECI differs
+    /// from the one in effect. The `count` of the segments is not used.
+    pub fn encode_segments(segments: &[Segment], ecc_level: EccLevel) -> Result<Code, EncodeError> {
+        for segment in segments {
+            check_segment(segment)?;
+        }
+
+        Self::encode_with(ecc_level, |_| segments.to_vec())
+    }
+
+    /// Encode the segments given for each version in the smallest version
+    /// which can hold them. The mask is chosen by the penalty rules of
+    /// ISO/IEC 18004.
+    fn encode_with<F>(ecc_level: EccLevel, segments_for: F) -> Result<Code, EncodeError>
+    where
+        F: Fn(usize) -> Vec<Segment>,
+    {
+        let mut symbol = Data {
+            version: VERSION_MIN,
+            ..Default::default()
+        };
+        let segments = loop {
+            let (data_bytes, _, _) = data_layout(&symbol, ecc_level);
+            let segments = segments_for(symbol.version);
+            if let Some(bits) = segments_bits(&segments, &symbol)? {
+                if bits <= data_bytes * 8 {
+                    break segments;
+                }
+            }
+            if symbol.version == VERSION_MAX {
+                return Err(EncodeError::DataTooLong);
+            }
+            symbol.version += 1;
+        };
+        let version = symbol.version;
+
+        let stream = codestream(&segments, &symbol, ecc_level);
+        let size = version as i32 * 4 + 17;
+        let cells = data_cells(&symbol, size, size);
+
+        let mut base = Matrix::new(size, size);
+        function_patterns(&mut base, version);
+
+        let mut best: Option<(i32, Matrix)> = None;
+        for mask in 0..8 {
+            let mut matrix = base.clone();
+            matrix.place_data(&cells, &stream, mask);
+            format_info(&mut matrix, ecc_level, mask);
+
+            let score = penalty(&matrix);
+            if best.as_ref().is_none_or(|(best, _)| score < *best) {
+                best = Some((score, matrix));
+            }
+        }
+        let (_, matrix) = best.unwrap();
+
+        Ok(matrix.into_code(CodeKind::Qr))
+    }
+
+    /// Encode a sequence of segments as an rMQR code of the given
+    /// version, from 1 for R7x43 to 32 for R17x139, like
+    /// `encode_segments`. rMQR codes only have the error correction
+    /// levels `M` and `H`.
+    pub fn encode_rmqr(
+        segments: &[Segment],
+        version: usize,
+        ecc_level: EccLevel,
+    ) -> Result<Code, EncodeError> {
+        if !(RMQR_VERSION_MIN..=RMQR_VERSION_MAX).contains(&version) {
+            return Err(EncodeError::InvalidVersion(version));
+        }
+        if ecc_level != EccLevel::M && ecc_level != EccLevel::H {
+            return Err(EncodeError::UnsupportedEccLevel(ecc_level));
+        }
+        for segment in segments {
+            check_segment(segment)?;
+        }
+
+        let symbol = Data {
+            kind: CodeKind::Rmqr,
+            version,
+            ..Default::default()
+        };
+        let (data_bytes, _, _) = data_layout(&symbol, ecc_level);
+        match segments_bits(segments, &symbol)? {
+            Some(bits) if bits <= data_bytes * 8 => {}
+            _ => return Err(EncodeError::DataTooLong),
+        }
+
+        let info = &RMQR_VERSION_DB[version - 1];
+        let stream = codestream(segments, &symbol, ecc_level);
+        let cells = data_cells(&symbol, info.width, info.height);
+
+        let mut matrix = Matrix::new(info.width, info.height);
+        rmqr_function_patterns(&mut matrix, info);
+        rmqr_format_info(&mut matrix, info, version, ecc_level);
+        matrix.place_data(&cells, &stream, data_mask(&symbol));
+
+        Ok(matrix.into_code(CodeKind::Rmqr))
+    }
+}
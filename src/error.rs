@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::quirc::{DataType, EccLevel};
+
 #[derive(Debug, Error)]
 pub enum DecodeError {
     #[error("Invalid grid size")]
@@ -23,3 +25,53 @@ pub enum ExtractError {
     #[error("Out of bounds")]
     OutOfBounds,
 }
+
+#[derive(Debug, Error)]
+pub enum StructuredAppendError {
+    #[error("Not part of a structured append sequence")]
+    NotStructuredAppend,
+    #[error("Invalid symbol index")]
+    InvalidIndex,
+    #[error("Conflicting data for symbol {0}")]
+    Conflict(u8),
+    #[error("Parity mismatch")]
+    ParityMismatch,
+}
+
+#[derive(Debug, Error)]
+pub enum Gs1Error {
+    #[error("Invalid application identifier {0:?}")]
+    InvalidApplicationIdentifier(String),
+    #[error("Truncated element string")]
+    Truncated,
+    #[error("Invalid value for application identifier {0}")]
+    InvalidValue(String),
+    #[error("Check digit mismatch for application identifier {0}")]
+    CheckDigit(String),
+}
+
+#[derive(Debug, Error)]
+pub enum GradeError {
+    #[error("Image size does not match")]
+    ImageSize,
+    #[error(transparent)]
+    Extract(#[from] ExtractError),
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+}
+
+#[derive(Debug, Error)]
+pub enum EncodeError {
+    #[error("Data too long for any version")]
+    DataTooLong,
+    #[error("Data can not be encoded in {0} mode")]
+    InvalidData(DataType),
+    #[error("Unsupported mode {0}")]
+    UnsupportedMode(DataType),
+    #[error("Invalid ECI assignment {0}")]
+    InvalidEci(u32),
+    #[error("Invalid version {0}")]
+    InvalidVersion(usize),
+    #[error("Unsupported ECC level {0:?}")]
+    UnsupportedEccLevel(EccLevel),
+}
@@ -0,0 +1,586 @@
+use crate::decode::{
+    data_cells, data_mask, mask_bit, misdecode_protection, BlockCorrection, Datastream,
+};
+use crate::error::{ExtractError, GradeError};
+use crate::identify::{
+    find_mesh_point, grid_transform, perspective_map, perspective_map_f64, Image, CELL_SAMPLES,
+};
+use crate::quirc::*;
+use crate::version_db::*;
+
+/// A print quality grade, as defined by ISO/IEC 15415. Grades are
+/// ordered from `F` up to `A`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Grade {
+    F,
+    D,
+    C,
+    B,
+    A,
+}
+
+impl Grade {
+    const PASSING: [Grade; 4] = [Grade::A, Grade::B, Grade::C, Grade::D];
+
+    /// Grade a value which must be at least the given thresholds for A,
+    /// B, C and D.
+    fn at_least(value: f64, thresholds: [f64; 4]) -> Grade {
+        Self::PASSING
+            .iter()
+            .zip(&thresholds)
+            .find(|(_, t)| value >= **t)
+            .map_or(Grade::F, |(grade, _)| *grade)
+    }
+
+    /// Grade a value which must be at most the given thresholds for A,
+    /// B, C and D.
+    fn at_most(value: f64, thresholds: [f64; 4]) -> Grade {
+        Self::PASSING
+            .iter()
+            .zip(&thresholds)
+            .find(|(_, t)| value <= **t)
+            .map_or(Grade::F, |(grade, _)| *grade)
+    }
+}
+
+/// A measured quality parameter and its grade.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Graded {
+    pub value: f64,
+    pub grade: Grade,
+}
+
+/// The print quality of a symbol, measured along the lines of ISO/IEC
+/// 15415 and ISO/IEC 29158.
+///
+/// The measurements are made on the image as it was captured, rather
+/// than with the calibrated optics the standards require, so the grades
+/// are an indication of quality and not a verification.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QualityReport {
+    /// The difference between the highest and lowest reflectance in the
+    /// symbol and its quiet zone, as a share of the full range.
+    pub symbol_contrast: Graded,
+    /// The mean modulation of the data modules, i.e. how far their
+    /// reflectance is from the global threshold relative to the symbol
+    /// contrast. The grade also depends on how much error correction is
+    /// left to make up for poorly modulated codewords.
+    pub modulation: Graded,
+    /// Like `modulation`, but negative for modules which are on the
+    /// wrong side of the global threshold.
+    pub reflectance_margin: Graded,
+    /// The difference between the horizontal and vertical module size,
+    /// relative to their mean.
+    pub axial_nonuniformity: Graded,
+    /// The largest distance in modules of an alignment pattern from
+    /// where the grid puts it. Zero for symbols without alignment
+    /// patterns.
+    pub grid_nonuniformity: Graded,
+    /// The share of modules of the finder, separator and timing patterns
+    /// which have the wrong colour, counting the finder sub-pattern of
+    /// rMQR codes as a finder pattern. The grade is that of the worst
+    /// pattern.
+    pub fixed_pattern_damage: Graded,
+    /// The share of the error correction capacity which was not needed
+    /// to decode the worst block.
+    pub unused_error_correction: Graded,
+    /// The overall grade, which is the lowest of all the grades.
+    pub grade: Grade,
+}
+
+const CONTRAST_GRADES: [f64; 4] = [0.70, 0.55, 0.40, 0.20];
+const MODULATION_GRADES: [f64; 4] = [0.50, 0.40, 0.30, 0.20];
+const UEC_GRADES: [f64; 4] = [0.62, 0.50, 0.37, 0.25];
+const AXIAL_GRADES: [f64; 4] = [0.06, 0.08, 0.10, 0.12];
+const GRID_GRADES: [f64; 4] = [0.38, 0.50, 0.63, 0.75];
+const FINDER_GRADES: [f64; 4] = [0.0, 1.0, 2.0, 3.0];
+const TIMING_GRADES: [f64; 4] = [0.0, 0.07, 0.14, 0.20];
+
+/// The reflectance of each cell of a grid and of the ring of quiet zone
+/// cells around it.
+struct Reflectance {
+    width: i32,
+    height: i32,
+    cells: Vec<Option<f64>>,
+    /// The difference between the highest and lowest reflectance, as a
+    /// share of the full range.
+    contrast: f64,
+    /// The global threshold between dark and light.
+    threshold: f64,
+    /// The range of reflectance, which is never zero.
+    range: f64,
+}
+
+impl Reflectance {
+    fn get(&self, x: i32, y: i32) -> Option<f64> {
+        if x < -1 || y < -1 || x > self.width || y > self.height {
+            return None;
+        }
+        self.cells[((y + 1) * (self.width + 2) + x + 1) as usize]
+    }
+
+    fn modulation(&self, x: i32, y: i32) -> f64 {
+        self.get(x, y)
+            .map_or(0.0, |r| 2.0 * (r - self.threshold).abs() / self.range)
+    }
+
+    fn margin(&self, x: i32, y: i32, dark: bool) -> f64 {
+        self.get(x, y).map_or(0.0, |r| {
+            let m = 2.0 * (r - self.threshold) / self.range;
+            if dark {
+                -m
+            } else {
+                m
+            }
+        })
+    }
+
+    fn is_dark(&self, x: i32, y: i32) -> Option<bool> {
+        self.get(x, y).map(|r| r < self.threshold)
+    }
+}
+
+/// Reflectance of a pixel, where dark modules of the grid are always low.
+fn pixel_reflectance(qr: &Grid, value: u8) -> f64 {
+    if qr.inverted {
+        255.0 - value as f64
+    } else {
+        value as f64
+    }
+}
+
+/// The mean reflectance of a cell, sampled at the same points as
+/// `Sampling::Vote` reads it. Returns `None` if the cell is out of image
+/// bounds.
+fn cell_reflectance(qr: &Grid, image: &[u8], w: usize, h: usize, x: i32, y: i32) -> Option<f64> {
+    let mut sum = 0.0;
+    let mut n = 0;
+    let mut p = Point::default();
+
+    for v in &CELL_SAMPLES {
+        for u in &CELL_SAMPLES {
+            let (u, v) = (x as f64 + *u, y as f64 + *v);
+            perspective_map(grid_transform(qr, u, v), u, v, &mut p);
+            if p.y < 0 || p.y >= h as i32 || p.x < 0 || p.x >= w as i32 {
+                continue;
+            }
+            sum += pixel_reflectance(qr, image[p.y as usize * w + p.x as usize]);
+            n += 1;
+        }
+    }
+
+    if n == 0 {
+        None
+    } else {
+        Some(sum / n as f64)
+    }
+}
+
+fn measure_reflectance(qr: &Grid, image: &[u8], w: usize, h: usize) -> Reflectance {
+    let (width, height) = (qr.grid_size, qr.grid_height);
+    let mut cells = Vec::with_capacity(((width + 2) * (height + 2)) as usize);
+    for y in -1..=height {
+        for x in -1..=width {
+            cells.push(cell_reflectance(qr, image, w, h, x, y));
+        }
+    }
+
+    let (min, max) = cells
+        .iter()
+        .flatten()
+        .fold((255.0f64, 0.0f64), |(min, max), r| {
+            (min.min(*r), max.max(*r))
+        });
+
+    Reflectance {
+        width,
+        height,
+        cells,
+        contrast: (max - min).max(0.0) / 255.0,
+        threshold: (max + min) / 2.0,
+        range: (max - min).max(1.0),
+    }
+}
+
+/// The share of the error correction capacity of a block which is left
+/// after correcting the given numbers of errors and erasures.
+fn unused_ecc(block: &BlockCorrection, errors: usize, erasures: usize, protection: usize) -> f64 {
+    let used = (2 * errors + erasures) as f64;
+    let capacity = block.ecc_codewords as f64 - protection as f64;
+    if capacity <= 0.0 {
+        /* Blocks which can only detect errors are either intact or not */
+        return if used == 0.0 { 1.0 } else { 0.0 };
+    }
+
+    (1.0 - used / capacity).max(0.0)
+}
+
+/// Grade a parameter which is measured per codeword. At each grade
+/// level, the codewords with a lower grade are counted as erasures, and
+/// the grade is limited by the error correction left to correct them
+/// and the remaining errors. The symbol gets the best of these grades.
+fn codeword_grade(blocks: &[BlockCorrection], grades: &[Vec<Grade>], protection: usize) -> Grade {
+    Grade::PASSING
+        .iter()
+        .map(|level| {
+            let uec = blocks
+                .iter()
+                .zip(grades)
+                .map(|(block, codewords)| {
+                    let erased = |i: &usize| codewords[*i] < *level;
+                    let erasures = (0..codewords.len()).filter(erased).count();
+                    let errors = block.errors.iter().filter(|i| !erased(i)).count();
+                    unused_ecc(block, errors, erasures, protection)
+                })
+                .fold(1.0, f64::min);
+
+            (*level).min(Grade::at_least(uec, UEC_GRADES))
+        })
+        .max()
+        .unwrap_or(Grade::F)
+}
+
+/// The finder pattern with its top-left corner at `(x, y)`, along with
+/// its separator if there is one, as cells which should be dark or
+/// light. Cells outside the grid are left out.
+fn finder_cells(qr: &Grid, x: i32, y: i32, separator: bool) -> Vec<(i32, i32, bool)> {
+    let mut cells = Vec::new();
+    for j in -1..=7 {
+        for i in -1..=7 {
+            let inside = (0..7).contains(&i) && (0..7).contains(&j);
+            let (cx, cy) = (x + i, y + j);
+            if !(inside || separator)
+                || cx < 0
+                || cy < 0
+                || cx >= qr.grid_size
+                || cy >= qr.grid_height
+            {
+                continue;
+            }
+
+            let ring = (i - 3).abs().max((j - 3).abs());
+            cells.push((cx, cy, inside && ring != 2));
+        }
+    }
+
+    cells
+}
+
+/// The cells of the finder sub-pattern of an rMQR code, in its bottom
+/// right corner.
+fn sub_finder_cells(qr: &Grid) -> Vec<(i32, i32, bool)> {
+    let (x, y) = (qr.grid_size - 5, qr.grid_height - 5);
+    let mut cells = Vec::new();
+    for j in 0..5 {
+        for i in 0..5 {
+            let ring = (i - 2_i32).abs().max((j - 2_i32).abs());
+            cells.push((x + i, y + j, ring != 1));
+        }
+    }
+
+    cells
+}
+
+/// A timing pattern along row or column `line`, from `start` up to
+/// `end`.
+fn timing_cells(line: i32, start: i32, end: i32, vertical: bool) -> Vec<(i32, i32, bool)> {
+    (start..end)
+        .map(|i| {
+            if vertical {
+                (line, i, i % 2 == 0)
+            } else {
+                (i, line, i % 2 == 0)
+            }
+        })
+        .collect()
+}
+
+fn fixed_pattern_damage(qr: &Grid, refl: &Reflectance) -> Graded {
+    let size = qr.grid_size;
+    let (finders, timing) = match qr.kind {
+        CodeKind::Qr => (
+            vec![
+                finder_cells(qr, 0, 0, true),
+                finder_cells(qr, size - 7, 0, true),
+                finder_cells(qr, 0, size - 7, true),
+            ],
+            vec![
+                timing_cells(6, 8, size - 8, false),
+                timing_cells(6, 8, size - 8, true),
+            ],
+        ),
+        CodeKind::MicroQr => (
+            vec![finder_cells(qr, 0, 0, true)],
+            vec![
+                timing_cells(0, 8, size, false),
+                timing_cells(0, 8, size, true),
+            ],
+        ),
+        CodeKind::Rmqr => {
+            let (w, h) = (size, qr.grid_height);
+            let apat: &[i32] = match rmqr_version(h, w) {
+                Some(version) => &RMQR_VERSION_DB[version - RMQR_VERSION_MIN].apat,
+                None => &[],
+            };
+            let columns: Vec<i32> = apat.iter().copied().take_while(|x| *x != 0).collect();
+
+            /* The bottom timing pattern starts after the finder pattern
+             * in R7 codes, and after the corner finder pattern from R11.
+             * Both edge timing patterns are broken by the alignment
+             * patterns, which are joined by vertical timing patterns.
+             */
+            let bottom = match h {
+                7 => 8,
+                9 => 1,
+                _ => 2,
+            };
+            let edge = |line, start, end| -> Vec<_> {
+                timing_cells(line, start, end, false)
+                    .into_iter()
+                    .filter(|(x, _, _)| columns.iter().all(|cx| (x - cx).abs() > 1))
+                    .collect()
+            };
+            let mut timing = vec![
+                edge(0, 8, w - 2),
+                edge(h - 1, bottom, w - 5),
+                timing_cells(w - 1, 2, h - 5, true),
+            ];
+            for &x in &columns {
+                timing.push(timing_cells(x, 3, h - 3, true));
+            }
+
+            (
+                vec![finder_cells(qr, 0, 0, true), sub_finder_cells(qr)],
+                timing,
+            )
+        }
+    };
+
+    let defects = |cells: &[(i32, i32, bool)]| {
+        cells
+            .iter()
+            .filter(|(x, y, dark)| refl.is_dark(*x, *y) != Some(*dark))
+            .count()
+    };
+
+    let mut grade = Grade::A;
+    let mut total = 0;
+    let mut damaged = 0;
+    for cells in &finders {
+        let n = defects(cells);
+        grade = grade.min(Grade::at_most(n as f64, FINDER_GRADES));
+        total += cells.len();
+        damaged += n;
+    }
+    for cells in timing.iter().filter(|cells| !cells.is_empty()) {
+        let n = defects(cells);
+        grade = grade.min(Grade::at_most(n as f64 / cells.len() as f64, TIMING_GRADES));
+        total += cells.len();
+        damaged += n;
+    }
+
+    Graded {
+        value: damaged as f64 / total as f64,
+        grade,
+    }
+}
+
+fn axial_nonuniformity(qr: &Grid) -> Graded {
+    let (w, h) = (qr.grid_size as f64, qr.grid_height as f64);
+    let corner = |u, v| perspective_map_f64(&qr.c, u, v);
+    let dist = |a: (f64, f64), b: (f64, f64)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+
+    let x =
+        (dist(corner(0.0, 0.0), corner(w, 0.0)) + dist(corner(0.0, h), corner(w, h))) / (2.0 * w);
+    let y =
+        (dist(corner(0.0, 0.0), corner(0.0, h)) + dist(corner(w, 0.0), corner(w, h))) / (2.0 * h);
+    let value = (x - y).abs() / ((x + y) / 2.0);
+
+    Graded {
+        value,
+        grade: Grade::at_most(value, AXIAL_GRADES),
+    }
+}
+
+/// Measure how far the alignment patterns of a QR-code are from where
+/// the perspective transform of the grid puts them. Patterns which can't
+/// be found are left out.
+fn grid_nonuniformity(qr: &Grid, image: &[u8], w: usize, h: usize, threshold: f64) -> Graded {
+    let mut value = 0.0f64;
+    if qr.kind != CodeKind::Qr {
+        return Graded {
+            value,
+            grade: Grade::A,
+        };
+    }
+
+    let version = ((qr.grid_size - 17) / 4) as usize;
+    let apat = &VERSION_DB[version.min(VERSION_MAX)].apat;
+    let count = apat.iter().take_while(|a| **a != 0).count();
+
+    if count > 0 {
+        let pixels: Vec<Pixel> = image[..w * h]
+            .iter()
+            .map(|p| (pixel_reflectance(qr, *p) < threshold) as Pixel)
+            .collect();
+        let img = Image {
+            pixels: &pixels,
+            width: w,
+            height: h,
+        };
+
+        for j in 0..count {
+            for i in 0..count {
+                /* Three of the positions are taken by capstones */
+                if matches!((i, j), (0, 0))
+                    || (i == 0 && j == count - 1)
+                    || (i == count - 1 && j == 0)
+                {
+                    continue;
+                }
+
+                let (x, y) = (apat[i] as f64, apat[j] as f64);
+                if let Some((du, dv)) = find_mesh_point(&qr.c, &img, x, y) {
+                    value = value.max((du * du + dv * dv).sqrt());
+                }
+            }
+        }
+    }
+
+    Graded {
+        value,
+        grade: Grade::at_most(value, GRID_GRADES),
+    }
+}
+
+/// Measure the modulation and reflectance margin of each data codeword,
+/// and grade both by the codeword method.
+fn data_modulation(
+    data: &Data,
+    ds: &Datastream,
+    cells: &[(i32, i32)],
+    refl: &Reflectance,
+    protection: usize,
+) -> (Graded, Graded) {
+    let mask = data_mask(data);
+    let mut mod_sum = 0.0;
+    let mut margin_sum = 0.0;
+    let mut n = 0;
+    let mut mod_grades = Vec::new();
+    let mut margin_grades = Vec::new();
+
+    for block in &ds.blocks {
+        let mut mods = Vec::new();
+        let mut margins = Vec::new();
+        for (start, len) in &block.bits {
+            let mut mod_grade = Grade::A;
+            let mut margin_grade = Grade::A;
+            for bit in *start..start + len {
+                let (x, y) = cells[bit as usize];
+                let value = ds.raw[(bit >> 3) as usize] & 0x80 >> (bit & 7) != 0;
+                let dark = value != (mask_bit(mask, y, x) != 0);
+                /* The cells of mirrored codes are those of the transposed
+                 * grid.
+                 */
+                let (x, y) = if data.mirrored { (y, x) } else { (x, y) };
+
+                let modulation = refl.modulation(x, y);
+                let margin = refl.margin(x, y, dark);
+                mod_grade = mod_grade.min(Grade::at_least(modulation, MODULATION_GRADES));
+                margin_grade = margin_grade.min(Grade::at_least(margin, MODULATION_GRADES));
+                mod_sum += modulation;
+                margin_sum += margin;
+                n += 1;
+            }
+            mods.push(mod_grade);
+            margins.push(margin_grade);
+        }
+        mod_grades.push(mods);
+        margin_grades.push(margins);
+    }
+
+    let n = n.max(1) as f64;
+    (
+        Graded {
+            value: mod_sum / n,
+            grade: codeword_grade(&ds.blocks, &mod_grades, protection),
+        },
+        Graded {
+            value: margin_sum / n,
+            grade: codeword_grade(&ds.blocks, &margin_grades, protection),
+        },
+    )
+}
+
+impl Quirc {
+    /// Grade the print quality of the QR-code specified by the given
+    /// index. `image` must be the grayscale image which was passed to
+    /// `identify`. Codes which can't be decoded can't be graded.
+    pub fn grade(&self, index: usize, image: &[u8]) -> Result<QualityReport, GradeError> {
+        if index >= self.count() {
+            return Err(ExtractError::OutOfBounds.into());
+        }
+        if image.len() < self.w * self.h {
+            return Err(GradeError::ImageSize);
+        }
+
+        let qr = &self.grids[index];
+        let code = self.extract(index)?;
+        let (data, ds) = code.decode_stream(true)?;
+        let height = if code.kind == CodeKind::Rmqr {
+            code.height
+        } else {
+            code.width
+        };
+        let cells = data_cells(&data, code.width, height);
+
+        let refl = measure_reflectance(qr, image, self.w, self.h);
+        let symbol_contrast = Graded {
+            value: refl.contrast,
+            grade: Grade::at_least(refl.contrast, CONTRAST_GRADES),
+        };
+
+        let protection = misdecode_protection(&data);
+        let (modulation, reflectance_margin) =
+            data_modulation(&data, &ds, &cells, &refl, protection);
+
+        let uec = ds
+            .blocks
+            .iter()
+            .map(|block| unused_ecc(block, block.errors.len(), block.erasures, protection))
+            .fold(1.0, f64::min);
+        let unused_error_correction = Graded {
+            value: uec,
+            grade: Grade::at_least(uec, UEC_GRADES),
+        };
+
+        let axial_nonuniformity = axial_nonuniformity(qr);
+        let grid_nonuniformity = grid_nonuniformity(qr, image, self.w, self.h, refl.threshold);
+        let fixed_pattern_damage = fixed_pattern_damage(qr, &refl);
+
+        let grade = [
+            symbol_contrast.grade,
+            modulation.grade,
+            reflectance_margin.grade,
+            axial_nonuniformity.grade,
+            grid_nonuniformity.grade,
+            fixed_pattern_damage.grade,
+            unused_error_correction.grade,
+        ]
+        .iter()
+        .copied()
+        .min()
+        .unwrap_or(Grade::F);
+
+        Ok(QualityReport {
+            symbol_contrast,
+            modulation,
+            reflectance_margin,
+            axial_nonuniformity,
+            grid_nonuniformity,
+            fixed_pattern_damage,
+            unused_error_correction,
+            grade,
+        })
+    }
+}
@@ -0,0 +1,123 @@
+//! Parsing of GS1 element strings, as carried by codes in FNC1 first
+//! position mode.
+
+use crate::error::Gs1Error;
+
+/// The group separator, which terminates variable length values.
+pub const GS: u8 = 0x1d;
+
+/// Number of digits of an application identifier, by its first two
+/// digits.
+fn ai_length(prefix: u8) -> Option<usize> {
+    match prefix {
+        0..=4 | 10..=22 | 30 | 37 | 90..=99 => Some(2),
+        23..=25 | 40..=42 | 71 => Some(3),
+        31..=36 | 39 | 43 | 70 | 72 | 80..=82 => Some(4),
+        _ => None,
+    }
+}
+
+/// Total length of identifier and value for application identifiers
+/// which are never followed by a separator, by their first two digits.
+fn predefined_length(prefix: u8) -> Option<usize> {
+    match prefix {
+        0 => Some(20),
+        1..=3 => Some(16),
+        4 => Some(18),
+        11..=19 => Some(8),
+        20 => Some(4),
+        31..=36 => Some(10),
+        41 => Some(16),
+        _ => None,
+    }
+}
+
+/// Verify the GS1 modulo 10 check digit, which is the last digit.
+fn check_digit_valid(digits: &[u8]) -> bool {
+    let (check, body) = match digits.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+
+    let sum: u32 = body
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| (d - b'0') as u32 * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+
+    (10 - sum % 10) % 10 == (check - b'0') as u32
+}
+
+fn validate(ai: &str, value: &[u8]) -> Result<(), Gs1Error> {
+    /* Values of predefined length are cut off at that length, so a
+     * separator in one means it was shorter.
+     */
+    if value.contains(&GS) {
+        return Err(Gs1Error::InvalidValue(ai.to_string()));
+    }
+
+    /* SSCC and GTINs are numeric, with a trailing check digit */
+    if let "00" | "01" | "02" | "03" = ai {
+        if !value.iter().all(u8::is_ascii_digit) {
+            return Err(Gs1Error::InvalidValue(ai.to_string()));
+        }
+        if !check_digit_valid(value) {
+            return Err(Gs1Error::CheckDigit(ai.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Split a GS1 element string into its application identifiers and
+/// values. Values of application identifiers with a predefined length
+/// must have that length, and check digits of SSCCs (AI 00) and GTINs
+/// (AI 01 to 03) are verified.
+pub fn parse_gs1(payload: &[u8]) -> Result<Vec<(String, String)>, Gs1Error> {
+    let mut elements = Vec::new();
+    let mut rest = payload;
+
+    while let Some(first) = rest.first() {
+        if *first == GS {
+            rest = &rest[1..];
+            continue;
+        }
+
+        let invalid = || {
+            let end = rest.len().min(4);
+            Gs1Error::InvalidApplicationIdentifier(String::from_utf8_lossy(&rest[..end]).into())
+        };
+
+        if rest.len() < 2 || !rest[..2].iter().all(u8::is_ascii_digit) {
+            return Err(invalid());
+        }
+        let prefix = (rest[0] - b'0') * 10 + rest[1] - b'0';
+        let ai_len = ai_length(prefix).ok_or_else(invalid)?;
+        if rest.len() < ai_len {
+            return Err(Gs1Error::Truncated);
+        }
+        if !rest[..ai_len].iter().all(u8::is_ascii_digit) {
+            return Err(invalid());
+        }
+
+        let end = match predefined_length(prefix) {
+            Some(len) if rest.len() < len => return Err(Gs1Error::Truncated),
+            Some(len) => len,
+            None => rest
+                .iter()
+                .skip(ai_len)
+                .position(|c| *c == GS)
+                .map_or(rest.len(), |pos| ai_len + pos),
+        };
+
+        let ai = String::from_utf8_lossy(&rest[..ai_len]).into_owned();
+        let value = &rest[ai_len..end];
+        validate(&ai, value)?;
+
+        elements.push((ai, String::from_utf8_lossy(value).into_owned()));
+        rest = &rest[end..];
+    }
+
+    Ok(elements)
+}
@@ -2,6 +2,9 @@
 
 use std::convert::TryFrom;
 
+use crate::decode::{
+    correct_format, correct_micro_format, correct_rmqr_format, rmqr_format_cell, RMQR_FORMAT_MASK,
+};
 use crate::error::ExtractError;
 use crate::quirc::*;
 use crate::version_db::*;
@@ -12,10 +15,19 @@ struct Neighbour {
     pub distance: f64,
 }
 
-#[derive(Copy, Clone)]
-struct NeighbourList {
-    pub n: [Neighbour; 32],
-    pub count: usize,
+/// The detector limits for the current image, and which of them were hit.
+struct Limits {
+    config: DetectorConfig,
+    truncation: Truncation,
+    rejections: Vec<Rejection>,
+}
+
+impl Limits {
+    fn reject(&mut self, rejection: Rejection) {
+        if self.config.diagnostics {
+            self.rejections.push(rejection);
+        }
+    }
 }
 
 struct PolygonScoreData<'a> {
@@ -58,14 +70,21 @@ fn line_intersect(p0: &Point, p1: &Point, q0: &Point, q1: &Point, r: &mut Point)
 }
 
 fn perspective_setup(c: &mut [f64; 8], rect: &[Point; 4], w: f64, h: f64) {
-    let x0 = rect[0].x as f64;
-    let y0 = rect[0].y as f64;
-    let x1 = rect[1].x as f64;
-    let y1 = rect[1].y as f64;
-    let x2 = rect[2].x as f64;
-    let y2 = rect[2].y as f64;
-    let x3 = rect[3].x as f64;
-    let y3 = rect[3].y as f64;
+    let rect = [
+        (rect[0].x as f64, rect[0].y as f64),
+        (rect[1].x as f64, rect[1].y as f64),
+        (rect[2].x as f64, rect[2].y as f64),
+        (rect[3].x as f64, rect[3].y as f64),
+    ];
+
+    perspective_setup_f64(c, &rect, w, h);
+}
+
+fn perspective_setup_f64(c: &mut [f64; 8], rect: &[(f64, f64); 4], w: f64, h: f64) {
+    let (x0, y0) = rect[0];
+    let (x1, y1) = rect[1];
+    let (x2, y2) = rect[2];
+    let (x3, y3) = rect[3];
 
     let wden = w * (x2 * y3 - x3 * y2 + (x3 - x2) * y1 + x1 * (y2 - y3));
     let hden = h * (x2 * y3 + x1 * (y2 - y3) - x3 * y2 + (x3 - x2) * y1);
@@ -91,13 +110,36 @@ fn perspective_setup(c: &mut [f64; 8], rect: &[Point; 4], w: f64, h: f64) {
     c[7] = (-x2 * y3 + x1 * y3 + x3 * y2 + x0 * (y1 - y2) - x3 * y1 + (x2 - x1) * y0) / hden;
 }
 
-fn perspective_map(c: &[f64; 8], u: f64, v: f64, ret: &mut Point) {
+pub(crate) fn perspective_map(c: &[f64; 8], u: f64, v: f64, ret: &mut Point) {
+    let (x, y) = perspective_map_f64(c, u, v);
+
+    ret.x = x.round() as i32;
+    ret.y = y.round() as i32;
+}
+
+pub(crate) fn perspective_map_f64(c: &[f64; 8], u: f64, v: f64) -> (f64, f64) {
     let den = c[6] * u + c[7] * v + 1.0f64;
     let x = (c[0] * u + c[1] * v + c[2]) / den;
     let y = (c[3] * u + c[4] * v + c[5]) / den;
 
-    ret.x = x.round() as i32;
-    ret.y = y.round() as i32;
+    (x, y)
+}
+
+/// Returns the transform which maps `(u, v)` to where `c` maps
+/// `(u - u0, v - v0)`.
+fn perspective_translate(c: &[f64; 8], u0: f64, v0: f64) -> [f64; 8] {
+    let d = 1.0 - c[6] * u0 - c[7] * v0;
+
+    [
+        c[0] / d,
+        c[1] / d,
+        (c[2] - c[0] * u0 - c[1] * v0) / d,
+        c[3] / d,
+        c[4] / d,
+        (c[5] - c[3] * u0 - c[4] * v0) / d,
+        c[6] / d,
+        c[7] / d,
+    ]
 }
 
 fn perspective_unmap(c: &[f64; 8], in_0: &Point, u: &mut f64, v: &mut f64) {
@@ -225,55 +267,6 @@ fn flood_fill_seed<F>(
     }
 }
 
-// --- Adaptive thresholding
-
-fn otsu(q: &Quirc, image: &[u8]) -> u8 {
-    let num_pixels = q.w * q.h;
-
-    // Calculate histogram
-    let mut histogram: [u32; 256] = [0; 256];
-
-    for value in image {
-        let value = *value as usize;
-        histogram[value] = histogram[value].wrapping_add(1);
-    }
-
-    // Calculate weighted sum of histogram values
-    let mut sum: u32 = 0;
-    for (i, val) in histogram.iter().enumerate() {
-        sum = sum.wrapping_add((i as u32).wrapping_mul(*val));
-    }
-
-    // Compute threshold
-    let mut sum_b: i32 = 0;
-    let mut q1: i32 = 0;
-    let mut max = 0_f64;
-    let mut threshold = 0_u8;
-    for (i, val) in histogram.iter().enumerate() {
-        // Weighted background
-        q1 = (q1 as u32).wrapping_add(*val) as i32;
-        if q1 == 0 {
-            continue;
-        }
-        // Weighted foreground
-        let q2 = num_pixels as i32 - q1;
-        if q2 == 0 {
-            break;
-        }
-        sum_b = (sum_b as u32).wrapping_add((i as u32).wrapping_mul(*val)) as i32;
-        let m1 = sum_b as f64 / q1 as f64;
-        let m2 = (sum as f64 - sum_b as f64) / q2 as f64;
-        let m1m2 = m1 - m2;
-        let variance = m1m2 * m1m2 * q1 as f64 * q2 as f64;
-        if variance >= max {
-            threshold = i as u8;
-            max = variance
-        }
-    }
-
-    threshold
-}
-
 fn area_count(user_data: &mut UserData<'_>, _y: usize, left: i32, right: i32) {
     if let UserData::Region(ref mut region) = user_data {
         region.count += right - left + 1;
@@ -282,7 +275,13 @@ fn area_count(user_data: &mut UserData<'_>, _y: usize, left: i32, right: i32) {
     }
 }
 
-fn region_code(image: &mut ImageMut<'_>, regions: &mut Vec<Region>, x: i32, y: usize) -> i32 {
+fn region_code(
+    image: &mut ImageMut<'_>,
+    regions: &mut Vec<Region>,
+    limits: &mut Limits,
+    x: i32,
+    y: usize,
+) -> i32 {
     if x < 0 || x >= image.width as i32 || y >= image.height {
         return -1;
     }
@@ -295,7 +294,8 @@ fn region_code(image: &mut ImageMut<'_>, regions: &mut Vec<Region>, x: i32, y: u
     }
     let region = regions.len() as i32;
 
-    if region >= 65_534 {
+    if region >= 65_534 || region as usize >= limits.config.max_regions {
+        limits.truncation.regions = true;
         return -1;
     }
 
@@ -415,10 +415,12 @@ fn record_capstone(
     image: &mut ImageMut<'_>,
     regions: &mut [Region],
     capstones: &mut Vec<Capstone>,
+    limits: &mut Limits,
     ring: Pixel,
     stone: i32,
 ) {
-    if capstones.len() >= 32 {
+    if capstones.len() >= limits.config.max_capstones {
+        limits.truncation.capstones = true;
         return;
     }
     let cs_index = capstones.len() as i32;
@@ -449,22 +451,35 @@ fn test_capstone(
     image: &mut ImageMut<'_>,
     regions: &mut Vec<Region>,
     capstones: &mut Vec<Capstone>,
+    limits: &mut Limits,
     x: i32,
     y: usize,
     pb: &[i32],
 ) {
-    let ring_right = region_code(image, regions, x - pb[4], y);
-    let stone = region_code(image, regions, x - pb[4] - pb[3] - pb[2], y);
-    let ring_left = region_code(image, regions, x - pb[4] - pb[3] - pb[2] - pb[1] - pb[0], y);
+    let ring_right = region_code(image, regions, limits, x - pb[4], y);
+    let stone = region_code(image, regions, limits, x - pb[4] - pb[3] - pb[2], y);
+    let ring_left = region_code(
+        image,
+        regions,
+        limits,
+        x - pb[4] - pb[3] - pb[2] - pb[1] - pb[0],
+        y,
+    );
     if ring_left < 0 || ring_right < 0 || stone < 0 {
         return;
     }
+    let position = Point {
+        x: x - pb[4] - pb[3] - pb[2] / 2,
+        y: y as i32,
+    };
     /* Left and ring of ring should be connected */
     if ring_left != ring_right {
+        limits.reject(Rejection::RingDisconnected { position });
         return;
     }
     /* Ring should be disconnected from stone */
     if ring_left == stone {
+        limits.reject(Rejection::StoneConnected { position });
         return;
     }
     let stone_reg = &regions[stone as usize];
@@ -477,16 +492,23 @@ fn test_capstone(
     /* Ratio should ideally be 37.5 */
     let ratio = stone_reg.count * 100 / ring_reg.count;
     if !(10..=70).contains(&ratio) {
+        limits.reject(Rejection::RingStoneRatio {
+            position,
+            stone: stone_reg.count,
+            ring: ring_reg.count,
+            ratio,
+        });
         return;
     }
 
-    record_capstone(image, regions, capstones, ring_left as Pixel, stone);
+    record_capstone(image, regions, capstones, limits, ring_left as Pixel, stone);
 }
 
 fn finder_scan(
     image: &mut ImageMut<'_>,
     regions: &mut Vec<Region>,
     capstones: &mut Vec<Capstone>,
+    limits: &mut Limits,
     y: usize,
 ) {
     static CHECK: [i32; 5] = [1, 1, 3, 1, 1];
@@ -522,7 +544,7 @@ fn finder_scan(
                 }
 
                 if ok != 0 {
-                    test_capstone(image, regions, capstones, x as i32, y, &pb);
+                    test_capstone(image, regions, capstones, limits, x as i32, y, &pb);
                 }
             }
         }
@@ -536,6 +558,7 @@ fn find_alignment_pattern(
     image: &mut ImageMut<'_>,
     capstones: &[Capstone],
     regions: &mut Vec<Region>,
+    limits: &mut Limits,
     qr: &mut Grid,
 ) {
     let c0 = &capstones[qr.caps[0]];
@@ -569,7 +592,7 @@ fn find_alignment_pattern(
 
     while step_size * step_size < size_estimate * 100 {
         for _ in 0..step_size {
-            let code = region_code(image, regions, b.x, b.y as usize);
+            let code = region_code(image, regions, limits, b.x, b.y as usize);
             if code >= 0 {
                 let reg = &regions[code as usize];
                 if reg.count >= size_estimate / 2 && reg.count <= size_estimate * 2 {
@@ -712,35 +735,153 @@ fn measure_timing_pattern(qr: &mut Grid, capstones: &[Capstone], image: &Image<'
     let size = scan * 2 + 13;
     let ver = (size - 15) / 4;
     qr.grid_size = ver * 4 + 17;
+    qr.grid_height = qr.grid_size;
 
     0
 }
 
+/// Read one of the version information blocks next to a capstone, using
+/// the capstone's own perspective transform. The top-right block lies to
+/// the left of capstone C, and the bottom-left block, which is stored
+/// transposed, lies above capstone A.
+fn read_version_info(
+    qr: &Grid,
+    capstones: &[Capstone],
+    image: &Image<'_>,
+    which: usize,
+) -> Option<usize> {
+    let cap = &capstones[qr.caps[if which != 0 { 0 } else { 2 }]];
+    let mut bits = 0_u32;
+
+    for i in (0..18).rev() {
+        let (a, b) = ((i % 3) as f64 - 4.0, (i / 3) as f64);
+        let score = if which != 0 {
+            fitness_point(&cap.c, image, b, a)
+        } else {
+            fitness_point(&cap.c, image, a, b)
+        };
+        bits = bits << 1 | (score > 0) as u32;
+    }
+
+    correct_version_info(bits)
+}
+
+/// The timing pattern can be miscounted by a module or two on larger
+/// grids. From version 7 on, the version is also encoded in two BCH
+/// protected blocks, which are used to correct the grid size.
+fn check_version_info(qr: &mut Grid, capstones: &[Capstone], image: &Image<'_>) {
+    /* Blocks may be read from a code that doesn't have them when the
+     * estimate is one version short, so only trust a single block if the
+     * estimate says it should be there.
+     */
+    if qr.grid_size < (VERSION_INFO_MIN as i32 - 1) * 4 + 17 {
+        return;
+    }
+
+    let versions = [
+        read_version_info(qr, capstones, image, 0),
+        read_version_info(qr, capstones, image, 1),
+    ];
+    let version = match versions {
+        [Some(a), Some(b)] if a == b => a,
+        [Some(a), None] | [None, Some(a)] if qr.grid_size >= VERSION_INFO_MIN as i32 * 4 + 17 => a,
+        _ => return,
+    };
+
+    qr.grid_size = version as i32 * 4 + 17;
+    qr.grid_height = qr.grid_size;
+}
+
+/// Offsets within a cell of the points sampled when a single pixel isn't
+/// enough, both when fitting a grid to the image and when reading cells.
+pub(crate) static CELL_SAMPLES: [f64; 3] = [0.3, 0.5, 0.7];
+
 /// Read a cell from a grid using the currently set perspective
 /// transform. Returns +/- 1 for black/white, 0 for cells which are
 /// out of image bounds.
+///
+/// With `Sampling::Vote`, the cell is sampled at a 3x3 grid of points
+/// and takes the value of the majority, so that a single speck can't
+/// flip it.
 fn read_cell(q: &Quirc, index: usize, x: i32, y: i32) -> i32 {
-    let qr = &q.grids[index];
+    let center = read_point(q, index, x as f64 + 0.5, y as f64 + 0.5);
+    if center == 0 || q.sampling == Sampling::Center {
+        return center;
+    }
+
+    let mut score = 0;
+    for v in &CELL_SAMPLES {
+        for u in &CELL_SAMPLES {
+            score += read_point(q, index, x as f64 + *u, y as f64 + *v);
+        }
+    }
+
+    match score {
+        0 => center,
+        s if s > 0 => 1,
+        _ => -1,
+    }
+}
 
+/// Read the pixel at `(u, v)` in grid coordinates. Returns +/- 1 for
+/// black/white, 0 for points which are out of image bounds.
+fn read_point(q: &Quirc, index: usize, u: f64, v: f64) -> i32 {
+    let qr = &q.grids[index];
     let mut p = Point::default();
 
-    perspective_map(&qr.c, x as f64 + 0.5f64, y as f64 + 0.5f64, &mut p);
+    perspective_map(grid_transform(qr, u, v), u, v, &mut p);
     if p.y < 0 || p.y >= q.h as i32 || p.x < 0 || p.x >= q.w as i32 {
         return 0;
     }
 
-    if q.pixels[(p.y * q.w as i32 + p.x) as usize] != 0 {
+    /* The pixels may hold the other polarity if several passes were run */
+    let set = q.pixels[(p.y * q.w as i32 + p.x) as usize] != 0;
+    if set != (qr.inverted != q.pixels_inverted) {
         1
     } else {
         -1
     }
 }
 
+/// The transform used to read the point `(u, v)` of a grid.
+pub(crate) fn grid_transform(qr: &Grid, u: f64, v: f64) -> &[f64; 8] {
+    match &qr.mesh {
+        Some(mesh) => mesh.transform(u, v),
+        None => &qr.c,
+    }
+}
+
+/// Cells with a lower confidence than this are treated as erasures by
+/// `Quirc::extract_with_confidence`.
+const ERASURE_CONFIDENCE: u8 = 128;
+
+/// Measure how far the value `read_cell` gives for a cell can be trusted
+/// by sampling a 3x3 grid of points around the centre of the cell. The
+/// confidence is the share of the samples which agree with the value,
+/// scaled to 0..=255.
+fn cell_confidence(q: &Quirc, index: usize, x: i32, y: i32) -> u8 {
+    let value = read_cell(q, index, x, y);
+    if value == 0 {
+        return 0;
+    }
+
+    let mut agree = 0;
+    for v in &CELL_SAMPLES {
+        for u in &CELL_SAMPLES {
+            if read_point(q, index, x as f64 + *u, y as f64 + *v) == value {
+                agree += 1;
+            }
+        }
+    }
+
+    (agree * 255 / 9) as u8
+}
+
 #[derive(Debug)]
-struct Image<'a> {
-    pixels: &'a [Pixel],
-    width: usize,
-    height: usize,
+pub(crate) struct Image<'a> {
+    pub(crate) pixels: &'a [Pixel],
+    pub(crate) width: usize,
+    pub(crate) height: usize,
 }
 
 impl<'a> From<&'a Quirc> for Image<'a> {
@@ -764,15 +905,19 @@ impl<'a> From<&'a ImageMut<'a>> for Image<'a> {
 }
 
 fn fitness_cell(qr: &Grid, image: &Image<'_>, x: i32, y: i32) -> i32 {
-    static OFFSETS: [f64; 3] = [0.3, 0.5, 0.7];
+    fitness_point(&qr.c, image, x as f64, y as f64)
+}
 
+/// Score a cell at `(x, y)` in the coordinates of the given perspective
+/// transform by sampling several points within it.
+fn fitness_point(c: &[f64; 8], image: &Image<'_>, x: f64, y: f64) -> i32 {
     let mut score = 0;
     let mut p = Point::default();
 
-    for v in &OFFSETS {
-        for u in &OFFSETS {
+    for v in &CELL_SAMPLES {
+        for u in &CELL_SAMPLES {
             p.clear();
-            perspective_map(&qr.c, x as f64 + *u, y as f64 + *v, &mut p);
+            perspective_map(c, x + *u, y + *v, &mut p);
 
             if !(p.y < 0 || p.y >= image.height as i32 || p.x < 0 || p.x >= image.width as i32) {
                 if image.pixels[(p.y * image.width as i32 + p.x) as usize] != 0 {
@@ -819,6 +964,12 @@ const MAX_ALIGNMENT: usize = 7;
 /// transform, using the features we expect to find by scanning the
 /// grid.
 fn fitness_all(qr: &Grid, image: &Image<'_>) -> i32 {
+    match qr.kind {
+        CodeKind::MicroQr => return fitness_micro(qr, image),
+        CodeKind::Rmqr => return fitness_rmqr(qr, image),
+        CodeKind::Qr => {}
+    }
+
     let version = usize::try_from((qr.grid_size - 17) / 4).expect("invalid version");
     let info = &VERSION_DB[version];
     let mut score: i32 = 0;
@@ -862,8 +1013,399 @@ fn fitness_all(qr: &Grid, image: &Image<'_>) -> i32 {
     score
 }
 
+/// Like `fitness_all`, for Micro QR-codes. These only have a single
+/// capstone, and timing patterns along the top and left edges.
+fn fitness_micro(qr: &Grid, image: &Image<'_>) -> i32 {
+    let mut score: i32 = 0;
+
+    for i in 0..qr.grid_size - 7 {
+        let expect = if i & 1 != 0 { 1 } else { -1 };
+        score += fitness_cell(qr, image, i + 7, 0) * expect;
+        score += fitness_cell(qr, image, 0, i + 7) * expect;
+    }
+
+    score + fitness_capstone(qr, image, 0, 0)
+}
+
+/// Score how well the timing patterns and quiet zone of a Micro QR-code
+/// of the grid's size match the image, returning the number of cells
+/// which match and the number of cells tested.
+fn micro_frame_matches(qr: &Grid, image: &Image<'_>) -> (i32, i32) {
+    let mut matches = 0;
+    let mut total = 0;
+    let mut test = |x: i32, y: i32, dark: bool| {
+        if (fitness_cell(qr, image, x, y) > 0) == dark {
+            matches += 1;
+        }
+        total += 1;
+    };
+
+    for i in 7..qr.grid_size {
+        test(i, 0, i & 1 == 0);
+        test(0, i, i & 1 == 0);
+    }
+
+    /* The quiet zone is two modules wide */
+    for i in 0..qr.grid_size + 2 {
+        for j in qr.grid_size..qr.grid_size + 2 {
+            test(i, j, false);
+            test(j, i, false);
+        }
+    }
+
+    (matches, total)
+}
+
+/// Read the format information of a Micro QR-code, which is only valid
+/// if its symbol number agrees with the grid size. Mirrored codes hold
+/// the format information transposed.
+fn micro_format_valid(qr: &Grid, image: &Image<'_>) -> bool {
+    for transposed in &[false, true] {
+        let mut bits = 0_u16;
+        let cells = (1..=8).map(|i| (i, 8)).chain((1..=7).rev().map(|i| (8, i)));
+        for (x, y) in cells {
+            let (x, y) = if *transposed { (y, x) } else { (x, y) };
+            bits = bits << 1 | (fitness_cell(qr, image, x, y) > 0) as u16;
+        }
+
+        if let Ok((symbol, _, _)) = correct_micro_format(bits) {
+            if MICRO_SYMBOL_DB[symbol].version as i32 * 2 + 9 == qr.grid_size {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+/// Try to set up a Micro QR-code around a capstone which isn't part of a
+/// QR-code. Each rotation of the capstone and each size is scored by its
+/// timing patterns and quiet zone, and the best candidates are checked by
+/// reading their format information.
+fn test_micro(
+    image: &ImageMut<'_>,
+    capstones: &mut [Capstone],
+    grids: &mut Vec<Grid>,
+    limits: &mut Limits,
+    i: usize,
+) {
+    if capstones[i].qr_grid >= 0 {
+        return;
+    }
+
+    /* Finder-like patterns in the data of a QR-code are common */
+    let cap = &capstones[i];
+    if grids.iter().any(|grid| grid_contains(grid, &cap.center)) {
+        return;
+    }
+
+    let image = Image::from(image);
+    let mut candidates = Vec::new();
+
+    for rotation in 0..4 {
+        let mut corners = [Point::default(); 4];
+        for (j, corner) in corners.iter_mut().enumerate() {
+            *corner = cap.corners[(j + rotation) % 4];
+        }
+
+        let mut qr = Grid {
+            caps: [i; 3],
+            kind: CodeKind::MicroQr,
+            ..Default::default()
+        };
+        perspective_setup(&mut qr.c, &corners, 7.0, 7.0);
+
+        for size in (11..=17).step_by(2) {
+            qr.grid_size = size;
+            qr.grid_height = size;
+
+            /* The capstone alone gives a rough perspective, which is
+             * refined before the final score is taken.
+             */
+            let (matches, total) = micro_frame_matches(&qr, &image);
+            if matches * 4 < total * 3 {
+                continue;
+            }
+
+            let mut qr = qr;
+            jiggle_perspective(&mut qr, &image);
+            let (matches, total) = micro_frame_matches(&qr, &image);
+            if matches * 10 >= total * 9 {
+                candidates.push((matches * 1000 / total, qr));
+            }
+        }
+    }
+
+    candidates.sort_by_key(|(score, _)| -score);
+
+    for (_, qr) in candidates {
+        if !micro_format_valid(&qr, &image) {
+            continue;
+        }
+
+        if grids.len() >= limits.config.max_grids {
+            limits.truncation.grids = true;
+            return;
+        }
+
+        capstones[i].qr_grid = grids.len() as i32;
+        grids.push(qr);
+        return;
+    }
+}
+
+/// Try to set up an rMQR code around a capstone which isn't part of
+/// another code. For each rotation of the capstone, the height is chosen
+/// by scoring the frame around the capstone. The width is then found by
+/// following the timing patterns, refining the perspective as they get
+/// longer, until the right edge of the code is found.
+fn test_rmqr(
+    image: &ImageMut<'_>,
+    capstones: &mut [Capstone],
+    grids: &mut Vec<Grid>,
+    limits: &mut Limits,
+    i: usize,
+) {
+    if capstones[i].qr_grid >= 0 {
+        return;
+    }
+
+    let cap = &capstones[i];
+    if grids.iter().any(|grid| grid_contains(grid, &cap.center)) {
+        return;
+    }
+
+    let image = Image::from(image);
+
+    for rotation in 0..4 {
+        let mut corners = [Point::default(); 4];
+        for (j, corner) in corners.iter_mut().enumerate() {
+            *corner = cap.corners[(j + rotation) % 4];
+        }
+
+        let mut qr = Grid {
+            caps: [i; 3],
+            kind: CodeKind::Rmqr,
+            ..Default::default()
+        };
+        perspective_setup(&mut qr.c, &corners, 7.0, 7.0);
+
+        let best = (7..=17)
+            .step_by(2)
+            .map(|height| {
+                qr.grid_height = height;
+                let (matches, total) = rmqr_left_frame_matches(&qr, &image);
+                (matches * 1000 / total, height)
+            })
+            .max();
+        qr.grid_height = match best {
+            Some((score, height)) if score >= 800 => height,
+            _ => continue,
+        };
+
+        if let Some(qr) = rmqr_find_width(qr, &image) {
+            if grids.len() >= limits.config.max_grids {
+                limits.truncation.grids = true;
+                return;
+            }
+
+            capstones[i].qr_grid = grids.len() as i32;
+            grids.push(qr);
+            return;
+        }
+    }
+}
+
+/// Follow the timing patterns of an rMQR code of known height, trying
+/// each width in turn. The perspective is refined in steps short enough
+/// for the extrapolated timing patterns to stay within a module.
+fn rmqr_find_width(mut qr: Grid, image: &Image<'_>) -> Option<Grid> {
+    let height = qr.grid_height;
+    let mut reach = 7;
+
+    for info in RMQR_VERSION_DB.iter().filter(|info| info.height == height) {
+        while reach < info.width {
+            reach = (reach * 3 / 2).max(reach + 7).min(info.width);
+            qr.grid_size = reach;
+            jiggle_perspective_with(&mut qr, image, fitness_rmqr_timing);
+
+            let (matches, total) = rmqr_timing_matches(&qr, image);
+            if matches * 10 < total * 9 {
+                return None;
+            }
+        }
+
+        let (matches, total) = rmqr_right_frame_matches(&qr, image);
+        if matches * 10 >= total * 9 {
+            jiggle_perspective(&mut qr, image);
+            return if rmqr_format_valid(&qr, image) {
+                Some(qr)
+            } else {
+                None
+            };
+        }
+    }
+
+    None
+}
+
+/// Like `fitness_all`, for rMQR codes. The timing patterns along the top
+/// and bottom edges are broken by the alignment patterns, and the
+/// finder sub-pattern sits in the bottom right corner.
+fn fitness_rmqr(qr: &Grid, image: &Image<'_>) -> i32 {
+    let (width, height) = (qr.grid_size, qr.grid_height);
+    let apat = rmqr_version(height, width)
+        .map(|version| RMQR_VERSION_DB[version - 1].apat)
+        .unwrap_or_default();
+    let mut score = 0;
+
+    for x in 8..width - 5 {
+        if apat.iter().any(|p| *p != 0 && (p - x).abs() < 2) {
+            continue;
+        }
+        let expect = if x & 1 != 0 { -1 } else { 1 };
+        score += fitness_cell(qr, image, x, 0) * expect;
+        score += fitness_cell(qr, image, x, height - 1) * expect;
+    }
+
+    score + fitness_rmqr_left(qr, image) + fitness_apat(qr, image, width - 3, height - 3)
+}
+
+/// Score the timing patterns of an rMQR code up to the column
+/// `grid_size`, which needn't be the width of the code.
+fn fitness_rmqr_timing(qr: &Grid, image: &Image<'_>) -> i32 {
+    let mut score = 0;
+
+    for x in 8..qr.grid_size {
+        let expect = if x & 1 != 0 { -1 } else { 1 };
+        score += fitness_cell(qr, image, x, 0) * expect;
+        score += fitness_cell(qr, image, x, qr.grid_height - 1) * expect;
+    }
+
+    score + fitness_rmqr_left(qr, image)
+}
+
+/// Score the capstone of an rMQR code, and the timing pattern below it.
+fn fitness_rmqr_left(qr: &Grid, image: &Image<'_>) -> i32 {
+    let mut score = fitness_capstone(qr, image, 0, 0);
+
+    for y in 8..qr.grid_height - 2 {
+        let expect = if y & 1 != 0 { -1 } else { 1 };
+        score += fitness_cell(qr, image, 0, y) * expect;
+    }
+
+    score
+}
+
+/// Count the cells which match their expected colour, returning the
+/// number of matches and the number of cells tested.
+fn count_matches(qr: &Grid, image: &Image<'_>, cells: &[(i32, i32, bool)]) -> (i32, i32) {
+    let matches = cells
+        .iter()
+        .filter(|(x, y, dark)| (fitness_cell(qr, image, *x, *y) > 0) == *dark)
+        .count();
+
+    (matches as i32, cells.len() as i32)
+}
+
+/// Score the left end of an rMQR code of the grid's height: the timing
+/// patterns next to the capstone, and the quiet zone around them.
+fn rmqr_left_frame_matches(qr: &Grid, image: &Image<'_>) -> (i32, i32) {
+    let height = qr.grid_height;
+    let mut cells = Vec::new();
+
+    for x in 7..14 {
+        cells.push((x, 0, x & 1 == 0));
+        cells.push((x, height - 1, x & 1 == 0));
+    }
+    for y in 8..height - 2 {
+        cells.push((0, y, y & 1 == 0));
+    }
+
+    /* The quiet zone is two modules wide */
+    for x in -2..14 {
+        for y in &[-2, -1, height, height + 1] {
+            cells.push((x, *y, false));
+        }
+    }
+    for y in 0..height {
+        cells.push((-2, y, false));
+        cells.push((-1, y, false));
+    }
+
+    count_matches(qr, image, &cells)
+}
+
+/// Score the timing patterns of an rMQR code up to the column
+/// `grid_size`.
+fn rmqr_timing_matches(qr: &Grid, image: &Image<'_>) -> (i32, i32) {
+    let cells: Vec<_> = (8..qr.grid_size)
+        .flat_map(|x| vec![(x, 0, x & 1 == 0), (x, qr.grid_height - 1, x & 1 == 0)])
+        .collect();
+
+    count_matches(qr, image, &cells)
+}
+
+/// Score the right end of an rMQR code of the grid's size: the corner
+/// pattern, the finder sub-pattern, the timing pattern between them and
+/// the quiet zone.
+fn rmqr_right_frame_matches(qr: &Grid, image: &Image<'_>) -> (i32, i32) {
+    let (width, height) = (qr.grid_size, qr.grid_height);
+    let mut cells = vec![
+        (width - 4, 0, false),
+        (width - 3, 0, true),
+        (width - 2, 0, true),
+        (width - 2, 1, false),
+    ];
+
+    for y in 0..height - 5 {
+        cells.push((width - 1, y, y < 2 || y & 1 == 0));
+    }
+    for y in 0..5_i32 {
+        for x in 0..5_i32 {
+            let ring = (x - 2).abs().max((y - 2).abs());
+            cells.push((width - 5 + x, height - 5 + y, ring != 1));
+        }
+    }
+
+    for y in -1..=height {
+        cells.push((width, y, false));
+        cells.push((width + 1, y, false));
+    }
+
+    count_matches(qr, image, &cells)
+}
+
+/// Check that the format information of an rMQR code can be read from
+/// either copy, and that it agrees with the grid's size.
+fn rmqr_format_valid(qr: &Grid, image: &Image<'_>) -> bool {
+    let version = rmqr_version(qr.grid_height, qr.grid_size);
+
+    (0..2).any(|which| {
+        let mut bits = 0_u32;
+        for n in (0..18).rev() {
+            let (x, y) = rmqr_format_cell(qr.grid_size, qr.grid_height, which, n);
+            bits = bits << 1 | (fitness_cell(qr, image, x, y) > 0) as u32;
+        }
+
+        match correct_rmqr_format(bits ^ RMQR_FORMAT_MASK[which]) {
+            Ok((_, v, _)) => Some(v) == version,
+            Err(_) => false,
+        }
+    })
+}
+
 fn jiggle_perspective(qr: &mut Grid, image: &Image<'_>) {
-    let mut best = fitness_all(qr, image);
+    jiggle_perspective_with(qr, image, fitness_all);
+}
+
+/// Adjust the perspective transform to maximise the given fitness score.
+fn jiggle_perspective_with(
+    qr: &mut Grid,
+    image: &Image<'_>,
+    fitness: fn(&Grid, &Image<'_>) -> i32,
+) {
+    let mut best = fitness(qr, image);
     let mut adjustments: [f64; 8] = [0.; 8];
 
     for (a_val, c_val) in adjustments.iter_mut().zip(qr.c.iter()) {
@@ -877,7 +1419,7 @@ fn jiggle_perspective(qr: &mut Grid, image: &Image<'_>) {
             let step = adjustments[j];
             qr.c[j] = if i & 1 != 0 { old + step } else { old - step };
 
-            let test = fitness_all(qr, image);
+            let test = fitness(qr, image);
             if test > best {
                 best = test
             } else {
@@ -911,6 +1453,176 @@ fn setup_qr_perspective(qr: &mut Grid, capstones: &[Capstone], image: &Image<'_>
     );
 
     jiggle_perspective(qr, image);
+    setup_mesh(qr, capstones, image);
+}
+
+/// A single transform drifts away from the cells in the middle of large
+/// codes on curved or wrinkled surfaces. For grids with alignment
+/// patterns in their middle, locate every alignment pattern and set up a
+/// transform for each patch of the grid between them.
+fn setup_mesh(qr: &mut Grid, capstones: &[Capstone], image: &Image<'_>) {
+    qr.mesh = None;
+
+    let version = ((qr.grid_size - 17) / 4) as usize;
+    if qr.kind != CodeKind::Qr || !(VERSION_MIN..=VERSION_MAX).contains(&version) {
+        return;
+    }
+    let apat = &VERSION_DB[version].apat;
+    let count = apat.iter().take_while(|a| **a != 0).count();
+    if count < 3 {
+        return;
+    }
+
+    /* Find how far each alignment pattern is from where the transform
+     * puts it. The distances change slowly across the grid, so the
+     * search for each pattern starts from a guess based on those of
+     * its neighbours.
+     * Three of the positions are taken by capstones, which are placed
+     * using their own transforms instead.
+     */
+    let mut offsets = [[(0.0, 0.0); 7]; 7];
+    let mut centers = [[(0.0, 0.0); 7]; 7];
+    for j in 0..count {
+        for i in 0..count {
+            let (x, y) = (apat[i] as f64, apat[j] as f64);
+            let capstone = match (i, j) {
+                (0, 0) => Some((qr.caps[1], 6.5, 6.5)),
+                (i, 0) if i == count - 1 => Some((qr.caps[2], 0.5, 6.5)),
+                (0, j) if j == count - 1 => Some((qr.caps[0], 6.5, 0.5)),
+                _ => None,
+            };
+
+            if let Some((cap, u, v)) = capstone {
+                let (px, py) = perspective_map_f64(&capstones[cap].c, u, v);
+                let (mut gu, mut gv) = (0.0, 0.0);
+                let p = Point {
+                    x: px.round() as i32,
+                    y: py.round() as i32,
+                };
+                perspective_unmap(&qr.c, &p, &mut gu, &mut gv);
+                offsets[j][i] = (gu - x - 0.5, gv - y - 0.5);
+                centers[j][i] = (px, py);
+                continue;
+            }
+
+            let guess = match (i, j) {
+                (0, _) => offsets[j - 1][i],
+                (_, 0) => offsets[j][i - 1],
+                _ => (
+                    offsets[j][i - 1].0 + offsets[j - 1][i].0 - offsets[j - 1][i - 1].0,
+                    offsets[j][i - 1].1 + offsets[j - 1][i].1 - offsets[j - 1][i - 1].1,
+                ),
+            };
+
+            offsets[j][i] = match find_mesh_point(&qr.c, image, x + guess.0, y + guess.1) {
+                Some((du, dv)) => (guess.0 + du, guess.1 + dv),
+                None => guess,
+            };
+
+            let (du, dv) = offsets[j][i];
+            centers[j][i] = perspective_map_f64(&qr.c, x + du + 0.5, y + dv + 0.5);
+        }
+    }
+
+    let mut mesh = Mesh {
+        lines: *apat,
+        count,
+        ..Default::default()
+    };
+    for j in 0..count - 1 {
+        for i in 0..count - 1 {
+            let rect = [
+                centers[j][i],
+                centers[j][i + 1],
+                centers[j + 1][i + 1],
+                centers[j + 1][i],
+            ];
+            let mut c = [0.0; 8];
+            perspective_setup_f64(
+                &mut c,
+                &rect,
+                (apat[i + 1] - apat[i]) as f64,
+                (apat[j + 1] - apat[j]) as f64,
+            );
+            mesh.c[j][i] = perspective_translate(&c, apat[i] as f64 + 0.5, apat[j] as f64 + 0.5);
+        }
+    }
+
+    qr.mesh = Some(mesh);
+}
+
+/// Search for an alignment pattern within a cell and a half of the cell
+/// at `(x, y)`. Returns how far it is from that cell, or `None` if there
+/// is no clear match.
+pub(crate) fn find_mesh_point(
+    c: &[f64; 8],
+    image: &Image<'_>,
+    x: f64,
+    y: f64,
+) -> Option<(f64, f64)> {
+    /* A perfect match scores 9 for each of the 25 cells */
+    const MAX_SCORE: i32 = 225;
+    const MIN_SCORE: i32 = 150;
+
+    let score = |u: f64, v: f64| {
+        let mut score = 0;
+        for j in -2..=2_i32 {
+            for i in -2..=2_i32 {
+                let s = fitness_point(c, image, x + u + i as f64, y + v + j as f64);
+                score += if i.abs().max(j.abs()) == 1 { -s } else { s };
+            }
+        }
+        score
+    };
+
+    /* Unless the guess is a perfect match, search in steps of half a
+     * cell.
+     */
+    let mut coarse = (0.0, 0.0);
+    let mut best_score = score(0.0, 0.0);
+    if best_score < MAX_SCORE {
+        for dv in -3..=3 {
+            for du in -3..=3 {
+                let (u, v) = (du as f64 * 0.5, dv as f64 * 0.5);
+                let s = score(u, v);
+                if s > best_score {
+                    coarse = (u, v);
+                    best_score = s;
+                }
+            }
+        }
+    }
+
+    /* Refine in steps of a quarter cell. The best score is usually
+     * reached over a small area, so take its centre.
+     */
+    let mut scores = [[0; 5]; 5];
+    for (dv, row) in scores.iter_mut().enumerate() {
+        for (du, s) in row.iter_mut().enumerate() {
+            *s = score(
+                coarse.0 + (du as f64 - 2.0) * 0.25,
+                coarse.1 + (dv as f64 - 2.0) * 0.25,
+            );
+            best_score = best_score.max(*s);
+        }
+    }
+    if best_score < MIN_SCORE {
+        return None;
+    }
+
+    let mut sum = (0.0, 0.0);
+    let mut n = 0.0;
+    for (dv, row) in scores.iter().enumerate() {
+        for (du, s) in row.iter().enumerate() {
+            if *s == best_score {
+                sum.0 += (du as f64 - 2.0) * 0.25;
+                sum.1 += (dv as f64 - 2.0) * 0.25;
+                n += 1.0;
+            }
+        }
+    }
+
+    Some((coarse.0 + sum.0 / n, coarse.1 + sum.1 / n))
 }
 
 /// Rotate the capstone with so that corner 0 is the leftmost with respect
@@ -937,16 +1649,19 @@ fn rotate_capstone(cap: &mut Capstone, h0: &Point, hd: &Point) {
     perspective_setup(&mut cap.c, &cap.corners, 7.0, 7.0);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn record_qr_grid(
     image: &mut ImageMut<'_>,
     regions: &mut Vec<Region>,
     capstones: &mut [Capstone],
     grids: &mut Vec<Grid>,
+    limits: &mut Limits,
     mut a: usize,
     b: usize,
     mut c: usize,
 ) {
-    if grids.len() >= 8 {
+    if grids.len() >= limits.config.max_grids {
+        limits.truncation.grids = true;
         return;
     }
     /* Construct the hypotenuse line from A to C. B should be to
@@ -989,6 +1704,8 @@ fn record_qr_grid(
      * transform.
      */
     if measure_timing_pattern(qr, capstones, &Image::from(&*image)) >= 0 {
+        check_version_info(qr, capstones, &Image::from(&*image));
+
         /* Make an estimate based for the alignment pattern based on extending
          * lines from capstones A and C.
          */
@@ -1003,7 +1720,14 @@ fn record_qr_grid(
             /* On V2+ grids, we should use the alignment pattern. */
             if qr.grid_size > 21 {
                 /* Try to find the actual location of the alignment pattern. */
-                find_alignment_pattern(image, capstones, regions, qr);
+                find_alignment_pattern(image, capstones, regions, limits, qr);
+                if qr.align_region.is_none() {
+                    limits.reject(Rejection::AlignmentFallback {
+                        caps: qr.caps,
+                        grid_size: qr.grid_size,
+                        align: qr.align,
+                    });
+                }
                 /* Find the point of the alignment pattern closest to the
                  * top-left of the QR grid.
                  */
@@ -1051,6 +1775,15 @@ fn record_qr_grid(
             setup_qr_perspective(qr, capstones, &Image::from(&*image));
             return;
         }
+
+        limits.reject(Rejection::AlignmentEstimate { caps: qr.caps });
+    } else {
+        limits.reject(Rejection::TimingScan {
+            caps: qr.caps,
+            tpep: qr.tpep,
+            hscan: qr.hscan,
+            vscan: qr.vscan,
+        });
     }
 
     /* We've been unable to complete setup for this grid. Undo what we've
@@ -1063,22 +1796,24 @@ fn record_qr_grid(
     grids.pop();
 }
 
+#[allow(clippy::too_many_arguments)]
 fn test_neighbours(
     image: &mut ImageMut<'_>,
     regions: &mut Vec<Region>,
     capstones: &mut [Capstone],
     grids: &mut Vec<Grid>,
+    limits: &mut Limits,
     i: usize,
-    hlist: &NeighbourList,
-    vlist: &NeighbourList,
+    hlist: &[Neighbour],
+    vlist: &[Neighbour],
 ) {
     let mut best_score = 0.0;
     let mut best_h = -1;
     let mut best_v = -1;
 
     /* Test each possible grouping */
-    for hn in &hlist.n[..hlist.count] {
-        for vn in &vlist.n[0..vlist.count] {
+    for hn in hlist {
+        for vn in vlist {
             let score = (1.0 - hn.distance / vn.distance).abs();
 
             if score > 2.5 {
@@ -1094,6 +1829,21 @@ fn test_neighbours(
     }
 
     if best_h < 0 || best_v < 0 {
+        if limits.config.diagnostics {
+            let score = hlist
+                .iter()
+                .flat_map(|hn| {
+                    vlist
+                        .iter()
+                        .map(move |vn| (1.0 - hn.distance / vn.distance).abs())
+                })
+                .fold(f64::INFINITY, f64::min);
+            limits.reject(Rejection::NeighbourDistance {
+                capstone: i,
+                center: capstones[i].center,
+                score,
+            });
+        }
         return;
     }
 
@@ -1102,6 +1852,7 @@ fn test_neighbours(
         regions,
         capstones,
         grids,
+        limits,
         best_h as usize,
         i,
         best_v as usize,
@@ -1113,39 +1864,26 @@ fn test_grouping(
     regions: &mut Vec<Region>,
     capstones: &mut [Capstone],
     grids: &mut Vec<Grid>,
+    limits: &mut Limits,
     i: usize,
 ) {
-    let mut hlist = NeighbourList {
-        n: [Neighbour {
-            index: 0,
-            distance: 0.,
-        }; 32],
-        count: 0,
-    };
-    let mut vlist = NeighbourList {
-        n: [Neighbour {
-            index: 0,
-            distance: 0.,
-        }; 32],
-        count: 0,
-    };
+    let mut hlist = Vec::new();
+    let mut vlist = Vec::new();
 
     if capstones[i].qr_grid >= 0 {
         return;
     }
 
-    hlist.count = 0;
-    vlist.count = 0;
-
     /* Look for potential neighbours by examining the relative gradients
      * from this capstone to others.
      */
     let c1c = capstones[i].c;
+    let capstones_inverted = capstones[i].inverted;
     for (j, c2) in capstones.iter_mut().enumerate() {
         let mut u = 0.;
         let mut v = 0.;
 
-        if i == j || c2.qr_grid >= 0 {
+        if i == j || c2.qr_grid >= 0 || c2.inverted != capstones_inverted {
             continue;
         }
 
@@ -1154,39 +1892,336 @@ fn test_grouping(
         v = (v - 3.5).abs();
 
         if u < 0.2 * v {
-            let count = hlist.count;
-            hlist.count += 1;
-            let n = &mut hlist.n[count];
-            n.index = j as i32;
-            n.distance = v;
+            hlist.push(Neighbour {
+                index: j as i32,
+                distance: v,
+            });
         }
 
         if v < 0.2 * u {
-            let count = vlist.count;
-            vlist.count += 1;
-            let n = &mut vlist.n[count];
-            n.index = j as i32;
-            n.distance = u;
+            vlist.push(Neighbour {
+                index: j as i32,
+                distance: u,
+            });
         }
     }
 
-    if !(hlist.count != 0 && vlist.count != 0) {
+    if hlist.is_empty() || vlist.is_empty() {
+        limits.reject(Rejection::NotOrthogonal {
+            capstone: i,
+            center: capstones[i].center,
+            horizontal: hlist.len(),
+            vertical: vlist.len(),
+        });
         return;
     }
 
-    test_neighbours(image, regions, capstones, grids, i, &hlist, &vlist);
+    test_neighbours(image, regions, capstones, grids, limits, i, &hlist, &vlist);
 }
 
-fn pixels_setup(q: &mut Quirc, source: &[u8], threshold: u8) {
-    let dest = &mut q.pixels;
+/// Try to set up a QR-code around two capstones which aren't part of any
+/// code, for codes whose third capstone is damaged or covered. Each place
+/// the third capstone could be is tried in turn, and a grid is only kept
+/// if its timing patterns and format information can be read.
+#[allow(clippy::too_many_arguments)]
+fn test_pair(
+    image: &mut ImageMut<'_>,
+    regions: &mut Vec<Region>,
+    capstones: &mut Vec<Capstone>,
+    grids: &mut Vec<Grid>,
+    limits: &mut Limits,
+    i: usize,
+    count: usize,
+) {
+    if capstones[i].qr_grid >= 0 {
+        return;
+    }
+
+    /* Finder-like patterns in the data of a QR-code are common */
+    let center = capstones[i].center;
+    if grids.iter().any(|grid| grid_contains(grid, &center)) {
+        return;
+    }
 
-    for (value, dest) in source.iter().zip(dest.iter_mut()) {
-        *dest = if (*value as i32) < threshold as i32 {
-            1
+    for j in i + 1..count {
+        if capstones[j].qr_grid >= 0 || capstones[j].inverted != capstones[i].inverted {
+            continue;
+        }
+
+        let (mut u, mut v) = (0.0, 0.0);
+        perspective_unmap(&capstones[i].c, &capstones[j].center, &mut u, &mut v);
+        let (u, v) = (u - 3.5, v - 3.5);
+
+        /* The capstones of the smallest codes are 14 modules apart, and
+         * those of the largest 170.
+         */
+        let distance = u.abs().max(v.abs());
+        if !(13.0..=(VERSION_MAX * 4 + 12) as f64).contains(&distance) {
+            continue;
+        }
+
+        /* Both capstones are 7 modules across */
+        let (mut u0, mut v0) = (0.0, 0.0);
+        let (mut u2, mut v2) = (0.0, 0.0);
+        perspective_unmap(&capstones[i].c, &capstones[j].corners[0], &mut u0, &mut v0);
+        perspective_unmap(&capstones[i].c, &capstones[j].corners[2], &mut u2, &mut v2);
+        let size = ((u2 - u0).powi(2) + (v2 - v0).powi(2)).sqrt() / std::f64::consts::SQRT_2;
+        if !(3.5..=14.0).contains(&size) {
+            continue;
+        }
+
+        let center = capstones[j].center;
+        if grids.iter().any(|grid| grid_contains(grid, &center)) {
+            continue;
+        }
+
+        /* Offsets of the third capstone from capstone i, in modules, with
+         * the capstone at the corner of the code if it isn't the third.
+         */
+        let mut candidates = Vec::new();
+        if u.abs() < 0.2 * v.abs() || v.abs() < 0.2 * u.abs() {
+            for (pu, pv) in [(-v, u), (v, -u)] {
+                candidates.push(((pu, pv), Some(i)));
+                candidates.push(((u + pu, v + pv), Some(j)));
+            }
+        } else if (u.abs() / v.abs() - 1.0).abs() < 0.2 {
+            candidates.push(((u, 0.0), None));
+            candidates.push(((0.0, v), None));
+        }
+
+        /* Setting up a grid rotates the capstones, so keep the frame the
+         * offsets are measured in.
+         */
+        let cap = capstones[i];
+        for ((du, dv), corner) in candidates {
+            /* Before going to the trouble of setting up a grid, check
+             * the timing patterns from where capstone i puts them.
+             */
+            let centers = [(0.0, 0.0), (u, v), (du, dv)];
+            let (a, b, c) = match corner {
+                Some(b) if b == i => (1, 0, 2),
+                Some(_) => (0, 1, 2),
+                None => (0, 2, 1),
+            };
+            let timing_valid = pair_timing_valid(
+                &cap,
+                &Image::from(&*image),
+                centers[a],
+                centers[b],
+                centers[c],
+            );
+            if !timing_valid {
+                continue;
+            }
+
+            if capstones.len() >= limits.config.max_capstones {
+                limits.truncation.capstones = true;
+                return;
+            }
+            let k = capstones.len();
+            capstones.push(infer_capstone(&cap, du, dv));
+
+            let caps = [i, j, k];
+            let (a, b, c) = (caps[a], caps[b], caps[c]);
+            let first_grid = grids.len();
+            record_qr_grid(image, regions, capstones, grids, limits, a, b, c);
+            if grids.len() > first_grid {
+                if pair_grid_valid(&grids[first_grid], &Image::from(&*image)) {
+                    return;
+                }
+
+                grids.pop();
+                for cap in &mut capstones[..] {
+                    if cap.qr_grid == first_grid as i32 {
+                        cap.qr_grid = -1;
+                    }
+                }
+            }
+
+            capstones.pop();
+        }
+    }
+}
+
+/// Check the timing patterns running from the capstone at corner `b` to
+/// those at `a` and `c`. The centres of the capstones are given as
+/// offsets in modules from the given capstone, whose transform is used to
+/// read the cells.
+fn pair_timing_valid(
+    cap: &Capstone,
+    image: &Image<'_>,
+    a: (f64, f64),
+    b: (f64, f64),
+    c: (f64, f64),
+) -> bool {
+    let mut matches = 0;
+    let mut total = 0;
+
+    for (end, other) in [(a, c), (c, a)] {
+        let (du, dv) = (end.0 - b.0, end.1 - b.1);
+        let length = du.hypot(dv);
+        let (du, dv) = (du / length, dv / length);
+
+        /* The timing pattern runs along the inner edges of the capstones,
+         * 3 modules from their centres.
+         */
+        let (pu, pv) = (other.0 - b.0, other.1 - b.1);
+        let (nu, nv) = if du * pv - dv * pu > 0.0 {
+            (-dv, du)
         } else {
-            0
-        } as Pixel;
+            (dv, -du)
+        };
+
+        /* The capstones' own transforms may be a little off in scale,
+         * so fit the distance to an allowable grid size.
+         */
+        let modules = ((length - 14.0) / 4.0).round().max(0.0) as i32 * 4 + 14;
+        let scale = length / modules as f64;
+
+        for t in 5..=modules - 5 {
+            let (t_u, t_v) = (t as f64 * scale, 3.0 * scale);
+            let u = 3.5 + b.0 + du * t_u + nu * t_v;
+            let v = 3.5 + b.1 + dv * t_u + nv * t_v;
+            let dark = fitness_point(&cap.c, image, u - 0.5, v - 0.5) > 0;
+            if dark == (t % 2 != 0) {
+                matches += 1;
+            }
+            total += 1;
+        }
+    }
+
+    total > 0 && matches * 4 >= total * 3
+}
+
+/// Place a capstone where there is none in the image, at an offset in
+/// modules from another capstone.
+fn infer_capstone(cap: &Capstone, du: f64, dv: f64) -> Capstone {
+    static US: [f64; 4] = [0.0, 7.0, 7.0, 0.0];
+    static VS: [f64; 4] = [0.0, 0.0, 7.0, 7.0];
+
+    let mut inferred = Capstone {
+        ring: -1,
+        stone: -1,
+        qr_grid: -1,
+        inverted: cap.inverted,
+        inferred: true,
+        ..Default::default()
+    };
+
+    for (corner, (u, v)) in inferred.corners.iter_mut().zip(US.iter().zip(VS.iter())) {
+        perspective_map(&cap.c, u + du, v + dv, corner);
+    }
+    perspective_setup(&mut inferred.c, &inferred.corners, 7.0, 7.0);
+    perspective_map(&inferred.c, 3.5, 3.5, &mut inferred.center);
+
+    inferred
+}
+
+/// Check a grid set up around an inferred capstone. Most of its timing
+/// patterns must be in place, and one of the copies of the format
+/// information must be read without errors.
+fn pair_grid_valid(qr: &Grid, image: &Image<'_>) -> bool {
+    let size = qr.grid_size;
+    let timing: Vec<_> = (8..size - 8)
+        .flat_map(|i| [(i, 6, i % 2 == 0), (6, i, i % 2 == 0)])
+        .collect();
+    let (matches, total) = count_matches(qr, image, &timing);
+    if matches * 5 < total * 4 {
+        return false;
     }
+
+    let first = FORMAT_CELLS.iter().rev().copied();
+    let second = (0..7)
+        .map(|i| (8, size - 1 - i))
+        .chain((0..8).map(|i| (size - 8 + i, 8)));
+    let copies: [Vec<_>; 2] = [first.collect(), second.collect()];
+
+    copies.iter().any(|cells| {
+        let mut format = 0_u16;
+        for (x, y) in cells {
+            format = format << 1 | (fitness_cell(qr, image, *x, *y) > 0) as u16;
+        }
+        format ^= 0x5412;
+
+        let read = format;
+        correct_format(&mut format).is_ok() && format == read
+    })
+}
+
+/// The image handed to `Quirc`, before it has been turned into pixels.
+#[derive(Copy, Clone)]
+enum Source<'a> {
+    /// Grayscale, to be run through the configured binarizer.
+    Grayscale(&'a [u8]),
+    /// Already binarized, non-zero for dark pixels.
+    Binary(&'a [u8]),
+}
+
+fn pixels_setup(q: &mut Quirc, source: Source<'_>) {
+    match source {
+        Source::Grayscale(image) => {
+            q.binarizer.binarize(image, q.w, q.h, &mut q.pixels);
+
+            /* Values above 1 would be taken for region labels */
+            for val in q.pixels.iter_mut() {
+                *val = (*val != 0) as Pixel;
+            }
+        }
+        Source::Binary(image) => {
+            for (value, dest) in image.iter().zip(q.pixels.iter_mut()) {
+                *dest = if *value != 0 { 1 } else { 0 };
+            }
+        }
+    }
+
+    q.pixels_inverted = false;
+}
+
+/// Swap dark and light pixels. Region labels left by a previous pass
+/// count as dark, so the image is only binarized once.
+fn pixels_invert(q: &mut Quirc) {
+    for val in q.pixels.iter_mut() {
+        *val = (*val == 0) as Pixel;
+    }
+
+    q.pixels_inverted = !q.pixels_inverted;
+}
+
+/// Check whether the centre of grid `b` lies within the area covered by
+/// grid `a`.
+fn grids_overlap(a: &Grid, b: &Grid) -> bool {
+    let mut center = Point::default();
+    let (x, y) = (b.grid_size as f64 / 2.0, b.grid_height as f64 / 2.0);
+    perspective_map(&b.c, x, y, &mut center);
+
+    grid_contains(a, &center)
+}
+
+/// Check whether a point lies within the area covered by a grid.
+fn grid_contains(a: &Grid, center: &Point) -> bool {
+    let (width, height) = (a.grid_size as f64, a.grid_height as f64);
+    let mut corners = [Point::default(); 4];
+    perspective_map(&a.c, 0.0, 0.0, &mut corners[0]);
+    perspective_map(&a.c, width, 0.0, &mut corners[1]);
+    perspective_map(&a.c, width, height, &mut corners[2]);
+    perspective_map(&a.c, 0.0, height, &mut corners[3]);
+
+    let mut sign = 0;
+    for i in 0..4 {
+        let p0 = &corners[i];
+        let p1 = &corners[(i + 1) % 4];
+        let cross = (p1.x - p0.x) as i64 * (center.y - p0.y) as i64
+            - (p1.y - p0.y) as i64 * (center.x - p0.x) as i64;
+        let s = cross.signum();
+        if s != 0 {
+            if sign != 0 && s != sign {
+                return false;
+            }
+            sign = s;
+        }
+    }
+
+    true
 }
 
 impl Quirc {
@@ -1194,8 +2229,26 @@ impl Quirc {
     /// The locations and content of each
     /// code may be obtained using accessor functions described below.
     pub fn identify<'a>(&'a mut self, width: usize, height: usize, image: &[u8]) -> CodeIter<'a> {
+        self.identify_source(width, height, Source::Grayscale(image))
+    }
+
+    /// Like `identify`, but for an image which has already been binarized.
+    /// Each byte of `image` is a pixel, which is dark if it is non-zero.
+    pub fn identify_binary<'a>(
+        &'a mut self,
+        width: usize,
+        height: usize,
+        image: &[u8],
+    ) -> CodeIter<'a> {
+        self.identify_source(width, height, Source::Binary(image))
+    }
+
+    fn identify_source(&mut self, width: usize, height: usize, source: Source<'_>) -> CodeIter<'_> {
         self.resize(width, height);
 
+        let image = match source {
+            Source::Grayscale(image) | Source::Binary(image) => image,
+        };
         assert_eq!(
             self.w * self.h,
             image.len(),
@@ -1206,8 +2259,33 @@ impl Quirc {
         self.regions.push(Default::default());
         self.regions.push(Default::default());
 
-        let threshold = otsu(self, image);
-        pixels_setup(self, image, threshold);
+        pixels_setup(self, source);
+
+        match self.inversion {
+            Inversion::Normal => self.identify_pass(false),
+            Inversion::Inverted => {
+                pixels_invert(self);
+                self.identify_pass(true);
+            }
+            Inversion::Both => {
+                self.identify_pass(false);
+                pixels_invert(self);
+                self.identify_pass(true);
+            }
+        }
+
+        CodeIter {
+            quirc: self,
+            current: 0,
+        }
+    }
+
+    /// Search the pixels as they are, adding to the regions, capstones
+    /// and grids found by previous passes, so that indices into them stay
+    /// valid.
+    fn identify_pass(&mut self, inverted: bool) {
+        let first_capstone = self.capstones.len();
+        let first_grid = self.grids.len();
 
         let mut image = ImageMut {
             pixels: &mut self.pixels,
@@ -1216,24 +2294,80 @@ impl Quirc {
         };
         let regions = &mut self.regions;
         let capstones = &mut self.capstones;
+        let mut limits = Limits {
+            config: self.config,
+            truncation: self.truncation,
+            rejections: std::mem::take(&mut self.rejections),
+        };
 
         for i in 0..self.h {
-            finder_scan(&mut image, regions, capstones, i);
+            finder_scan(&mut image, regions, capstones, &mut limits, i);
+        }
+
+        for cap in &mut capstones[first_capstone..] {
+            cap.inverted = inverted;
         }
 
         let grids = &mut self.grids;
-        for i in 0..capstones.len() {
-            test_grouping(&mut image, regions, capstones, grids, i);
+        for i in first_capstone..capstones.len() {
+            test_grouping(&mut image, regions, capstones, grids, &mut limits, i);
+        }
+        /* The left end of an rMQR code can pass for a Micro QR-code, so
+         * rMQR codes, which are checked along their whole length, are
+         * tried first.
+         */
+        if self.config.rmqr {
+            for i in first_capstone..capstones.len() {
+                test_rmqr(&image, capstones, grids, &mut limits, i);
+            }
+        }
+        if self.config.micro_qr {
+            for i in first_capstone..capstones.len() {
+                test_micro(&image, capstones, grids, &mut limits, i);
+            }
+        }
+        /* QR-codes with a damaged or covered capstone are only looked
+         * for among the capstones which nothing else could use.
+         */
+        let count = capstones.len();
+        for i in first_capstone..count {
+            test_pair(&mut image, regions, capstones, grids, &mut limits, i, count);
         }
+        self.truncation = limits.truncation;
+        self.rejections = limits.rejections;
 
-        CodeIter {
-            quirc: self,
-            current: 0,
+        for grid in &mut grids[first_grid..] {
+            grid.inverted = inverted;
+        }
+
+        self.remove_duplicate_grids(first_grid);
+    }
+
+    /// Drop grids from `first` onwards which cover the same code as one
+    /// of the grids found before them.
+    fn remove_duplicate_grids(&mut self, first: usize) {
+        let mut i = first;
+        while i < self.grids.len() {
+            let grid = self.grids[i];
+            if !self.grids[..first].iter().any(|g| grids_overlap(g, &grid)) {
+                i += 1;
+                continue;
+            }
+
+            for cap_index in &grid.caps {
+                self.capstones[*cap_index].qr_grid = -1;
+            }
+            for cap in &mut self.capstones {
+                if cap.qr_grid > i as i32 {
+                    cap.qr_grid -= 1;
+                }
+            }
+            self.grids.remove(i);
         }
     }
 
     /// Extract the QR-code specified by the given index.
-    fn extract(&self, index: usize) -> Result<Code, ExtractError> {
+    pub(crate) fn extract(&self, index: usize) -> Result<Code, ExtractError> {
         let qr = self.grids[index];
         if index > self.count() {
             return Err(ExtractError::OutOfBounds);
@@ -1241,23 +2375,22 @@ impl Quirc {
 
         let mut code = Code::default();
 
+        let (width, height) = (qr.grid_size as f64, qr.grid_height as f64);
         perspective_map(&qr.c, 0.0, 0.0, &mut code.corners[0]);
-        perspective_map(&qr.c, qr.grid_size as f64, 0.0, &mut code.corners[1]);
-        perspective_map(
-            &qr.c,
-            qr.grid_size as f64,
-            qr.grid_size as f64,
-            &mut code.corners[2],
-        );
-        perspective_map(&qr.c, 0.0, qr.grid_size as f64, &mut code.corners[3]);
-        code.size = qr.grid_size;
+        perspective_map(&qr.c, width, 0.0, &mut code.corners[1]);
+        perspective_map(&qr.c, width, height, &mut code.corners[2]);
+        perspective_map(&qr.c, 0.0, height, &mut code.corners[3]);
+        code.width = qr.grid_size;
+        code.height = qr.grid_height;
+        code.kind = qr.kind;
 
         let mut i = 0;
-        for y in 0..qr.grid_size {
+        for y in 0..qr.grid_height {
             for x in 0..qr.grid_size {
-                if read_cell(self, index, x, y) > 0 {
-                    code.cell_bitmap[(i >> 3) as usize] =
-                        (code.cell_bitmap[(i >> 3) as usize] as i32 | 1 << (i & 7)) as u8
+                match read_cell(self, index, x, y) {
+                    0 => code.erasure_bitmap[(i >> 3) as usize] |= 1 << (i & 7),
+                    v if v > 0 => code.cell_bitmap[(i >> 3) as usize] |= 1 << (i & 7),
+                    _ => {}
                 }
                 i += 1;
             }
@@ -1265,6 +2398,43 @@ impl Quirc {
 
         Ok(code)
     }
+
+    /// Extract the QR-code specified by the given index, along with the
+    /// confidence of each of its cells. Cells with a low confidence are
+    /// marked in the erasure bitmap, so that decoding can correct more
+    /// damage.
+    ///
+    /// Codes are indexed from 0 up to `count()`, in the order in which
+    /// `identify` returns them.
+    pub fn extract_with_confidence(
+        &self,
+        index: usize,
+    ) -> Result<(Code, CellConfidence), ExtractError> {
+        if index >= self.count() {
+            return Err(ExtractError::OutOfBounds);
+        }
+
+        let mut code = self.extract(index)?;
+        let mut confidence = CellConfidence {
+            width: code.width,
+            height: code.height,
+            cells: Vec::with_capacity((code.width * code.height) as usize),
+        };
+
+        let mut i = 0;
+        for y in 0..code.height {
+            for x in 0..code.width {
+                let c = cell_confidence(self, index, x, y);
+                if c < ERASURE_CONFIDENCE {
+                    code.erasure_bitmap[(i >> 3) as usize] |= 1 << (i & 7);
+                }
+                confidence.cells.push(c);
+                i += 1;
+            }
+        }
+
+        Ok((code, confidence))
+    }
 }
 
 pub struct CodeIter<'a> {
@@ -1272,6 +2442,14 @@ pub struct CodeIter<'a> {
     current: usize,
 }
 
+impl CodeIter<'_> {
+    /// Returns which detector limits were hit while identifying the
+    /// codes. If any were, codes may be missing from this iterator.
+    pub fn truncation(&self) -> Truncation {
+        self.quirc.truncation()
+    }
+}
+
 impl Iterator for CodeIter<'_> {
     type Item = Result<Code, ExtractError>;
 
@@ -24,13 +24,30 @@
 
 #![deny(clippy::all)]
 
+mod binarize;
+mod debug;
 mod decode;
+mod encode;
 mod error;
+mod grade;
+mod gs1;
 mod identify;
 mod quirc;
+mod render;
+mod structured_append;
+#[cfg(feature = "text")]
+mod text;
 mod version_db;
 
+pub use self::binarize::*;
+pub use self::debug::*;
 pub use self::error::*;
+pub use self::grade::*;
+pub use self::gs1::*;
 pub use self::identify::*;
 pub use self::quirc::*;
+pub use self::render::*;
+pub use self::structured_append::*;
+#[cfg(feature = "text")]
+pub use self::text::*;
 pub use self::version_db::*;
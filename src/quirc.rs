@@ -1,5 +1,9 @@
+use std::sync::Arc;
+
 use num_derive::{FromPrimitive, ToPrimitive};
 
+use crate::binarize::{Binarizer, Otsu};
+
 pub type Pixel = u16;
 
 #[derive(Debug, Clone)]
@@ -10,6 +14,21 @@ pub struct Quirc {
     pub regions: Vec<Region>,
     pub capstones: Vec<Capstone>,
     pub grids: Vec<Grid>,
+    /// Which polarities of the image are searched for QR-codes.
+    pub inversion: Inversion,
+    /// How the grayscale image is turned into black and white pixels.
+    pub binarizer: Arc<dyn Binarizer>,
+    /// Limits on the number of features the detector records.
+    pub config: DetectorConfig,
+    /// How the value of each cell is read from the image.
+    pub sampling: Sampling,
+    /// The limits which were hit while processing the last image.
+    pub(crate) truncation: Truncation,
+    /// Whether `pixels` currently holds the inverted binarization.
+    pub(crate) pixels_inverted: bool,
+    /// The candidates rejected while processing the last image, if
+    /// `DetectorConfig::diagnostics` is set.
+    pub(crate) rejections: Vec<Rejection>,
 }
 
 impl Default for Quirc {
@@ -21,6 +40,13 @@ impl Default for Quirc {
             regions: Vec::with_capacity(254),
             capstones: Vec::with_capacity(32),
             grids: Vec::with_capacity(8),
+            inversion: Inversion::default(),
+            binarizer: Arc::new(Otsu),
+            config: DetectorConfig::default(),
+            sampling: Sampling::default(),
+            truncation: Truncation::default(),
+            pixels_inverted: false,
+            rejections: Vec::new(),
         }
     }
 }
@@ -64,6 +90,186 @@ impl Quirc {
         self.regions.clear();
         self.capstones.clear();
         self.grids.clear();
+        self.truncation = Truncation::default();
+        self.pixels_inverted = false;
+        self.rejections.clear();
+    }
+
+    /// Returns which detector limits were hit while processing the last
+    /// image. If any were, codes may have been missed.
+    pub fn truncation(&self) -> Truncation {
+        self.truncation
+    }
+
+    /// Returns true if `pixels` holds the binarization of the inverted
+    /// image, i.e. light pixels are marked as set.
+    pub fn pixels_inverted(&self) -> bool {
+        self.pixels_inverted
+    }
+
+    /// Returns the candidates which were rejected, and the fallbacks
+    /// which were taken, while processing the last image, in the order
+    /// they happened. This is only recorded if
+    /// `DetectorConfig::diagnostics` is set, and is empty otherwise.
+    pub fn rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+}
+
+/// Settings of the detector used while identifying codes in an image.
+///
+/// The `max_` fields limit the number of features recorded. Each limit
+/// bounds the work done on noisy images; anything found beyond it is
+/// dropped and reported by `Quirc::truncation`. The other fields select
+/// the kinds of codes looked for, and whether diagnostics are recorded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DetectorConfig {
+    /// Maximum number of connected regions of dark pixels. Values above
+    /// 65534 have no effect, as regions are numbered within the pixels.
+    /// With `Inversion::Both`, the two polarities share this limit.
+    pub max_regions: usize,
+    /// Maximum number of finder patterns. Each QR-code uses three.
+    pub max_capstones: usize,
+    /// Maximum number of QR-codes.
+    pub max_grids: usize,
+    /// Look for Micro QR-codes among the finder patterns which are not
+    /// part of a QR-code.
+    pub micro_qr: bool,
+    /// Look for rMQR codes among the finder patterns which are not part
+    /// of a QR-code. Without this, the left end of an rMQR code may be
+    /// read as a Micro QR-code.
+    pub rmqr: bool,
+    /// Record why candidate finder patterns and QR-codes were rejected,
+    /// and where the detector fell back on an estimate, see
+    /// `Quirc::rejections`.
+    pub diagnostics: bool,
+}
+
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        Self {
+            max_regions: 65534,
+            max_capstones: 512,
+            max_grids: 128,
+            micro_qr: true,
+            rmqr: true,
+            diagnostics: false,
+        }
+    }
+}
+
+/// The detector limits which were hit while processing an image.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Truncation {
+    /// Regions were dropped because of `DetectorConfig::max_regions`.
+    pub regions: bool,
+    /// Finder patterns were dropped because of
+    /// `DetectorConfig::max_capstones`.
+    pub capstones: bool,
+    /// QR-codes were dropped because of `DetectorConfig::max_grids`.
+    pub grids: bool,
+}
+
+impl Truncation {
+    /// Returns true if any limit was hit.
+    pub fn any(&self) -> bool {
+        self.regions || self.capstones || self.grids
+    }
+}
+
+/// A candidate which the detector rejected, or a fallback it took,
+/// with the geometry involved. Capstones are indices into `Quirc::capstones`, and
+/// positions are in image coordinates.
+#[derive(Debug, Copy, Clone)]
+pub enum Rejection {
+    /// A run of pixels in the 1:1:3:1:1 ratios of a finder pattern, whose
+    /// outer runs are not part of the same ring. `position` is the centre
+    /// of the run in the middle.
+    RingDisconnected { position: Point },
+    /// A finder pattern candidate whose ring touches its stone.
+    StoneConnected { position: Point },
+    /// A finder pattern candidate whose stone doesn't cover 10 to 70
+    /// percent of the area of its ring. The ratio is ideally 37.5.
+    RingStoneRatio {
+        position: Point,
+        stone: i32,
+        ring: i32,
+        ratio: i32,
+    },
+    /// A capstone without another capstone lined up with it both along
+    /// its rows and along its columns, so it can't be the corner of a
+    /// QR-code. Such capstones may still be used for Micro QR and rMQR
+    /// codes, or for a QR-code with a damaged capstone.
+    NotOrthogonal {
+        capstone: usize,
+        center: Point,
+        horizontal: usize,
+        vertical: usize,
+    },
+    /// A capstone whose neighbours along its rows and columns are all at
+    /// too different distances to form a QR-code. `score` is the best
+    /// relative difference found, which must be at most 2.5.
+    NeighbourDistance {
+        capstone: usize,
+        center: Point,
+        score: f64,
+    },
+    /// A group of capstones between which neither timing pattern could
+    /// be read. `tpep` holds the end points of the scans.
+    TimingScan {
+        caps: [usize; 3],
+        tpep: [Point; 3],
+        hscan: i32,
+        vscan: i32,
+    },
+    /// A group of capstones whose outer edges are parallel, so the
+    /// position of the alignment pattern can't be estimated.
+    AlignmentEstimate { caps: [usize; 3] },
+    /// Not a rejection, but a fallback: no alignment pattern was found
+    /// near the estimate `align`, so the perspective of the QR-code was
+    /// set up from the estimate instead. The code is kept, but the
+    /// estimate is often inaccurate, so it may fail to decode.
+    AlignmentFallback {
+        caps: [usize; 3],
+        grid_size: i32,
+        align: Point,
+    },
+}
+
+/// Polarities of the image that are searched for QR-codes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Inversion {
+    /// Only look for dark modules on a light background.
+    Normal,
+    /// Only look for light modules on a dark background.
+    Inverted,
+    /// Look for both polarities. Codes which are found in both passes
+    /// are only reported once.
+    Both,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for Inversion {
+    fn default() -> Self {
+        Inversion::Normal
+    }
+}
+
+/// How the value of a cell is read from the image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Sampling {
+    /// Read the pixel at the centre of the cell. This is the fastest,
+    /// and works well for sharp images.
+    Center,
+    /// Read a 3x3 grid of pixels within the cell and take the value of
+    /// the majority. This is more robust to noise and blur.
+    Vote,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling::Center
     }
 }
 
@@ -76,7 +282,47 @@ pub struct Grid {
     pub hscan: i32,
     pub vscan: i32,
     pub grid_size: i32,
+    /// The number of rows, which only differs from `grid_size` for rMQR
+    /// codes.
+    pub grid_height: i32,
     pub c: [f64; 8],
+    /// Set if the grid was found in the inverted image.
+    pub inverted: bool,
+    /// The kind of symbol the grid was set up for.
+    pub kind: CodeKind,
+    /// Local transforms for large QR-codes, which are used instead of
+    /// `c` to read the cells.
+    pub mesh: Option<Mesh>,
+}
+
+/// Perspective transforms for the patches of a grid between its
+/// alignment patterns. Each transform maps grid coordinates to image
+/// coordinates, like `Grid::c`, but only fits the cells in and around
+/// its own patch.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Mesh {
+    /// The rows and columns of the centres of the alignment patterns,
+    /// which bound the patches.
+    pub lines: [i32; 7],
+    /// The number of entries in `lines`.
+    pub count: usize,
+    /// The transform for each patch, by row and then column.
+    pub c: [[[f64; 8]; 6]; 6],
+}
+
+impl Mesh {
+    /// The transform for the patch nearest to the point `(u, v)` in grid
+    /// coordinates.
+    pub fn transform(&self, u: f64, v: f64) -> &[f64; 8] {
+        let patch = |w: f64| {
+            self.lines[1..self.count - 1]
+                .iter()
+                .take_while(|line| **line as f64 <= w)
+                .count()
+        };
+
+        &self.c[patch(v)][patch(u)]
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -94,12 +340,19 @@ impl Point {
 
 #[derive(Debug, Copy, Clone, Default)]
 pub struct Capstone {
+    /// The region of the ring, or -1 if the capstone was inferred.
     pub ring: i32,
+    /// The region of the stone, or -1 if the capstone was inferred.
     pub stone: i32,
     pub corners: [Point; 4],
     pub center: Point,
     pub c: [f64; 8],
     pub qr_grid: i32,
+    /// Set if the capstone was found in the inverted image.
+    pub inverted: bool,
+    /// Set if the capstone wasn't found in the image, but inferred from
+    /// the other two capstones of a QR-code.
+    pub inferred: bool,
 }
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -115,23 +368,39 @@ pub struct Region {
 pub struct Code {
     /// The four corners of the QR-code, from top left, clockwise
     pub corners: [Point; 4],
-    /// The number of cells across in the QR-code. The cell bitmap
-    /// is a bitmask giving the actual values of cells. If the cell
-    /// at (x, y) is black, then the following bit is set:
+    /// The number of cells across.
+    pub width: i32,
+    /// The number of cells down. This is only different from the width
+    /// for rMQR codes.
+    pub height: i32,
+    /// The cell bitmap is a bitmask giving the actual values of cells. If
+    /// the cell at (x, y) is black, then the following bit is set:
     /// ```ignore
     ///     cell_bitmap[i >> 3] & (1 << (i & 7))
     /// ```
-    /// where i = (y * size) + x.
-    pub size: i32,
+    /// where i = (y * width) + x.
     pub cell_bitmap: [u8; 3917],
+    /// Cells whose value is unknown, with the same layout as
+    /// `cell_bitmap`. Extraction marks the cells which lie outside the
+    /// image, which are read as white, and
+    /// `Quirc::extract_with_confidence` also marks the cells with a low
+    /// confidence. Codewords holding any of these
+    /// cells are corrected as erasures, which takes half the error
+    /// correction capacity of an error at an unknown location.
+    pub erasure_bitmap: [u8; 3917],
+    /// The kind of symbol the cells were read from.
+    pub kind: CodeKind,
 }
 
 impl Default for Code {
     fn default() -> Self {
         Self {
             corners: [Point::default(); 4],
-            size: 0,
+            width: 0,
+            height: 0,
             cell_bitmap: [0; 3917],
+            erasure_bitmap: [0; 3917],
+            kind: CodeKind::default(),
         }
     }
 }
@@ -141,10 +410,36 @@ impl Code {
         for val in self.corners.iter_mut() {
             val.clear();
         }
-        self.size = 0;
+        self.width = 0;
+        self.height = 0;
         for val in self.cell_bitmap.iter_mut() {
             *val = 0;
         }
+        for val in self.erasure_bitmap.iter_mut() {
+            *val = 0;
+        }
+        self.kind = CodeKind::default();
+    }
+}
+
+/// How far each cell of an extracted code can be trusted, as returned
+/// by `Quirc::extract_with_confidence`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellConfidence {
+    /// The number of cells across.
+    pub width: i32,
+    /// The number of cells down.
+    pub height: i32,
+    /// One value per cell, with the cell at (x, y) at index
+    /// `y * width + x`. 255 means that the whole cell has the value that
+    /// was read, and 0 that the cell lies outside the image.
+    pub cells: Vec<u8>,
+}
+
+impl CellConfidence {
+    /// The confidence of the cell at (x, y).
+    pub fn get(&self, x: i32, y: i32) -> u8 {
+        self.cells[(y * self.width + x) as usize]
     }
 }
 
@@ -153,16 +448,112 @@ impl Code {
 pub struct Data {
     ///  Various parameters of the QR-code. These can mostly be  ignored
     /// if you only care about the data.
+    /// For Micro QR-codes, the version is 1 to 4 for M1 to M4, and the
+    /// mask is the Micro QR-code mask pattern from 0 to 3.
+    /// For rMQR codes, the version is 1 to 32 for R7x43 to R17x139, see
+    /// `RMQR_VERSION_DB`. The ECC level is either M or H.
     pub version: usize,
     pub ecc_level: EccLevel,
     pub mask: i32,
+    /// The kind of symbol the data was decoded from.
+    pub kind: CodeKind,
     /// This field is the highest-valued data type found in the QR code.
     pub data_type: Option<DataType>,
     /// Data payload. For the Kanji datatype, payload is encoded as Shift-JIS.
     /// For all other datatypes, payload is ASCII text.
     pub payload: Vec<u8>,
-    /// ECI assignment number
+    /// Character encoding from the last ECI designator, if it is one of
+    /// the known assignments.
     pub eci: Option<Eci>,
+    /// ECI assignment number of the last ECI designator.
+    pub eci_assignment: Option<u32>,
+    /// Set if the code could only be read after transposing its grid,
+    /// i.e. it was seen mirrored.
+    pub mirrored: bool,
+    /// Structured append header, if the code is one symbol of a
+    /// sequence.
+    pub structured_append: Option<StructuredAppend>,
+    /// FNC1 mode, if the data is formatted according to GS1 or another
+    /// industry specification.
+    pub fnc1: Option<Fnc1>,
+    /// The data segments making up the payload, in order. Unlike
+    /// `payload`, which joins all segments, this keeps the mode and ECI
+    /// of each segment, so mixed content can be interpreted correctly.
+    pub segments: Vec<Segment>,
+    /// Error correction statistics of each Reed-Solomon block, in the
+    /// order of the data codewords.
+    pub blocks: Vec<BlockStats>,
+    /// The copy of the format information which was used: 0 is the copy
+    /// around the top-left finder pattern, 1 the copy split between the
+    /// other two. rMQR codes have a copy next to the finder pattern and
+    /// one next to the finder sub-pattern, Micro QR-codes only have copy
+    /// 0.
+    pub format_copy: usize,
+    /// The number of bits of the format information which were
+    /// corrected.
+    pub format_errors: u32,
+}
+
+/// Error correction statistics of one Reed-Solomon block. A block which
+/// needs more and more correction across prints or scans is a sign of
+/// wear well before the code becomes unreadable.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BlockStats {
+    /// The number of codewords in the block.
+    pub codewords: usize,
+    /// The number of ECC codewords in the block.
+    pub ecc_codewords: usize,
+    /// The number of codewords which were corrected at unknown
+    /// locations.
+    pub errors: usize,
+    /// The number of codewords which were read from off-image or low
+    /// confidence cells, and corrected as erasures. Each erasure uses
+    /// up half as much capacity as an error.
+    pub erasures: usize,
+    /// The number of errors the block can correct. This leaves out the
+    /// ECC codewords of the smallest codes which only protect against
+    /// misdecoding, so M1 Micro QR-codes can only detect errors.
+    pub capacity: usize,
+}
+
+/// A single segment of QR-code data, encoded in one mode.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Segment {
+    /// The mode the segment is encoded in. This is one of `Numeric`,
+    /// `Alpha`, `Byte` or `Kanji`.
+    pub mode: DataType,
+    /// The ECI in effect for this segment.
+    pub eci: Option<Eci>,
+    /// The ECI assignment number in effect for this segment.
+    pub eci_assignment: Option<u32>,
+    /// The character count from the segment header. For Kanji, this is
+    /// the number of double-byte characters.
+    pub count: usize,
+    /// The decoded bytes, encoded in the same way as `Data::payload`.
+    pub data: Vec<u8>,
+}
+
+/// FNC1 modes, marking data formatted to an industry specification.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Fnc1 {
+    /// FNC1 in first position: the data is a GS1 element string, which
+    /// can be parsed using `parse_gs1`.
+    First,
+    /// FNC1 in second position, with the application indicator of the
+    /// specification. Values below 100 stand for two digits, other
+    /// values are an ASCII letter plus 100.
+    Second(u8),
+}
+
+/// Position of a symbol within a structured append sequence.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct StructuredAppend {
+    /// Zero-based position of this symbol in the sequence.
+    pub index: u8,
+    /// Number of symbols in the sequence, between 1 and 16.
+    pub total: u8,
+    /// XOR of all bytes of the complete message.
+    pub parity: u8,
 }
 
 /// Obtain the library version string.
@@ -170,6 +561,25 @@ pub fn version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
+/// The kinds of symbols that can be recognized.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CodeKind {
+    /// A QR-code, with three finder patterns.
+    Qr,
+    /// A Micro QR-code, with a single finder pattern.
+    MicroQr,
+    /// A rectangular Micro QR-code (rMQR), with a finder pattern at the
+    /// left and a smaller finder sub-pattern at the bottom right.
+    Rmqr,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for CodeKind {
+    fn default() -> Self {
+        CodeKind::Qr
+    }
+}
+
 /// QR-code ECC types.
 #[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, PartialEq, Eq, Hash)]
 pub enum EccLevel {
@@ -192,9 +602,12 @@ impl Default for EccLevel {
 pub enum DataType {
     Numeric = 1,
     Alpha = 2,
+    StructuredAppend = 3,
     Byte = 4,
+    Fnc1First = 5,
     Eci = 7,
     Kanji = 8,
+    Fnc1Second = 9,
 }
 
 impl std::fmt::Display for DataType {
@@ -202,19 +615,27 @@ impl std::fmt::Display for DataType {
         let x = match self {
             DataType::Numeric => "numeric",
             DataType::Alpha => "alpha",
+            DataType::StructuredAppend => "structured append",
             DataType::Byte => "byte",
+            DataType::Fnc1First => "fnc1 first",
             DataType::Eci => "eci",
             DataType::Kanji => "kanji",
+            DataType::Fnc1Second => "fnc1 second",
         };
         f.write_str(x)
     }
 }
 
-/// Common character encodings
+/// Character encodings, by their AIM ECI assignment number.
 #[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, PartialEq, Eq, Hash)]
 pub enum Eci {
+    /// Code page 437, legacy GLI designator.
+    Ibm437Legacy = 0,
+    /// ISO-8859-1, legacy GLI designator.
     Iso8859_1 = 1,
     Ibm437 = 2,
+    /// ISO-8859-1, the default interpretation.
+    Iso8859_1Aim = 3,
     Iso8859_2 = 4,
     Iso8859_3 = 5,
     Iso8859_4 = 6,
@@ -223,9 +644,31 @@ pub enum Eci {
     Iso8859_7 = 9,
     Iso8859_8 = 10,
     Iso8859_9 = 11,
+    Iso8859_10 = 12,
+    /// ISO-8859-11, which is Thai and compatible with Windows-874.
     Windows874 = 13,
     Iso8859_13 = 15,
+    Iso8859_14 = 16,
     Iso8859_15 = 17,
+    Iso8859_16 = 18,
     ShiftJis = 20,
+    Windows1250 = 21,
+    Windows1251 = 22,
+    Windows1252 = 23,
+    Windows1256 = 24,
+    Utf16Be = 25,
     Utf8 = 26,
+    Ascii = 27,
+    Big5 = 28,
+    Gb2312 = 29,
+    EucKr = 30,
+    Gbk = 31,
+    Gb18030 = 32,
+    Utf16Le = 33,
+    Utf32Be = 34,
+    Utf32Le = 35,
+    /// ISO/IEC 646 invariant characters.
+    Iso646Inv = 170,
+    /// 8-bit binary data.
+    Binary = 899,
 }
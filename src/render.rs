@@ -0,0 +1,155 @@
+use std::fmt::Write;
+
+use crate::quirc::Code;
+
+/// A grayscale image with one byte per pixel, stored row by row. The
+/// pixels can be handed to an image library as they are, e.g. to
+/// `image::GrayImage::from_raw`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LumaImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// The value of a cell as drawn by the renderers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Cell {
+    Light,
+    Dark,
+    Erased,
+}
+
+impl Code {
+    /// The value of the cell at (x, y). Cells outside the grid, such as
+    /// those of the quiet zone, are light.
+    pub(crate) fn render_cell(&self, x: i32, y: i32) -> Cell {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return Cell::Light;
+        }
+
+        let p = (y * self.width + x) as usize;
+        if self.erasure_bitmap[p >> 3] >> (p & 7) & 1 != 0 {
+            Cell::Erased
+        } else if self.cell_bitmap[p >> 3] >> (p & 7) & 1 != 0 {
+            Cell::Dark
+        } else {
+            Cell::Light
+        }
+    }
+
+    /// Render the code as an SVG image, with `module_size` units per cell
+    /// and a quiet zone of `quiet_zone` cells on each side.
+    ///
+    /// The cells are drawn as they are in `cell_bitmap`, so an extracted
+    /// code shows exactly what the decoder sampled. Erased cells are
+    /// drawn in gray.
+    pub fn to_svg(&self, module_size: u32, quiet_zone: u32) -> String {
+        let m = module_size as i32;
+        let q = quiet_zone as i32;
+        let (width, height) = ((self.width + 2 * q) * m, (self.height + 2 * q) * m);
+
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" \
+             width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\" \
+             shape-rendering=\"crispEdges\">\
+             <rect width=\"{width}\" height=\"{height}\" fill=\"#fff\"/>"
+        );
+
+        for (cell, color) in [(Cell::Dark, "#000"), (Cell::Erased, "#808080")] {
+            /* Join horizontal runs of cells into one rectangle */
+            let mut path = String::new();
+            for y in 0..self.height {
+                let mut x = 0;
+                while x < self.width {
+                    if self.render_cell(x, y) != cell {
+                        x += 1;
+                        continue;
+                    }
+                    let start = x;
+                    while x < self.width && self.render_cell(x, y) == cell {
+                        x += 1;
+                    }
+                    let _ = write!(
+                        path,
+                        "M{},{}h{}v{}h-{}z",
+                        (start + q) * m,
+                        (y + q) * m,
+                        (x - start) * m,
+                        m,
+                        (x - start) * m
+                    );
+                }
+            }
+
+            if !path.is_empty() {
+                let _ = write!(svg, "<path fill=\"{color}\" d=\"{path}\"/>");
+            }
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Render the code as a grayscale image, with `scale` pixels per cell
+    /// and a quiet zone of `quiet_zone` cells on each side. Dark cells are
+    /// 0, light cells 255 and erased cells 128.
+    pub fn to_luma(&self, scale: u32, quiet_zone: u32) -> LumaImage {
+        let s = scale as i32;
+        let q = quiet_zone as i32;
+        let (width, height) = ((self.width + 2 * q) * s, (self.height + 2 * q) * s);
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for py in 0..height {
+            for px in 0..width {
+                pixels.push(match self.render_cell(px / s - q, py / s - q) {
+                    Cell::Dark => 0,
+                    Cell::Erased => 128,
+                    Cell::Light => 255,
+                });
+            }
+        }
+
+        LumaImage {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        }
+    }
+
+    /// Render the code as text for a terminal, using Unicode half blocks
+    /// so that each character holds two rows of cells. There is a quiet
+    /// zone of `quiet_zone` cells on each side, and erased cells are
+    /// drawn like light cells.
+    ///
+    /// Blocks are drawn for the light cells, which shows the code the
+    /// right way round with light text on a dark background. Set
+    /// `invert` to draw the dark cells instead, for dark text on a light
+    /// background.
+    pub fn to_unicode(&self, quiet_zone: u32, invert: bool) -> String {
+        let q = quiet_zone as i32;
+        let filled = |x: i32, y: i32| {
+            /* Leave out the padding below an odd number of rows */
+            y < self.height + q && (self.render_cell(x, y) == Cell::Dark) == invert
+        };
+
+        let mut text = String::new();
+        let mut y = -q;
+        while y < self.height + q {
+            for x in -q..self.width + q {
+                text.push(match (filled(x, y), filled(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            text.push('\n');
+            y += 2;
+        }
+
+        text
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use crate::error::StructuredAppendError;
+use crate::quirc::Data;
+
+/// Reassembles messages which were split across several QR-codes using
+/// structured append.
+///
+/// Decoded symbols can be pushed in any order, and may come from several
+/// images, e.g. successive camera frames. Symbols are grouped into
+/// sequences by their total count and parity byte.
+#[derive(Debug, Clone, Default)]
+pub struct StructuredAppendAssembler {
+    sequences: HashMap<(u8, u8), Vec<Option<Vec<u8>>>>,
+}
+
+impl StructuredAppendAssembler {
+    /// Construct a new, empty assembler.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a decoded symbol. Once all symbols of its sequence have been
+    /// added, the joined payload is returned and the sequence is
+    /// forgotten. Adding the same symbol again is allowed.
+    pub fn push(&mut self, data: &Data) -> Result<Option<Vec<u8>>, StructuredAppendError> {
+        let header = data
+            .structured_append
+            .ok_or(StructuredAppendError::NotStructuredAppend)?;
+        if header.total == 0 || header.total > 16 || header.index >= header.total {
+            return Err(StructuredAppendError::InvalidIndex);
+        }
+
+        let key = (header.total, header.parity);
+        let parts = self
+            .sequences
+            .entry(key)
+            .or_insert_with(|| vec![None; header.total as usize]);
+
+        match &parts[header.index as usize] {
+            Some(payload) if *payload != data.payload => {
+                return Err(StructuredAppendError::Conflict(header.index));
+            }
+            Some(_) => return Ok(None),
+            None => parts[header.index as usize] = Some(data.payload.clone()),
+        }
+
+        if parts.iter().any(|part| part.is_none()) {
+            return Ok(None);
+        }
+
+        let payload: Vec<u8> = self
+            .sequences
+            .remove(&key)
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+
+        if payload.iter().fold(0, |parity, b| parity ^ b) != header.parity {
+            return Err(StructuredAppendError::ParityMismatch);
+        }
+
+        Ok(Some(payload))
+    }
+
+    /// Return the number of sequences which are still incomplete.
+    pub fn pending(&self) -> usize {
+        self.sequences.len()
+    }
+
+    /// Forget all symbols collected so far.
+    pub fn clear(&mut self) {
+        self.sequences.clear();
+    }
+}
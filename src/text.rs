@@ -0,0 +1,203 @@
+//! Conversion of decoded data into text.
+
+use encoding_rs::Encoding;
+
+use crate::quirc::{Data, DataType, Segment};
+
+/// Text decoded from a QR-code, see `Data::text`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Text {
+    /// The decoded text.
+    pub text: String,
+    /// Set if some of the data could not be decoded in its character set,
+    /// and was replaced by U+FFFD.
+    pub lossy: bool,
+}
+
+/// The upper half of code page 437. The lower half is ASCII.
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}', //
+];
+
+/// Character sets which can be selected by an ECI.
+#[derive(Debug, Copy, Clone)]
+enum Charset {
+    Latin1,
+    Cp437,
+    Utf32Be,
+    Utf32Le,
+    Other(&'static Encoding),
+    /// No ECI was given.
+    Default,
+}
+
+fn charset(eci_assignment: Option<u32>) -> Charset {
+    use encoding_rs::*;
+
+    let encoding = match eci_assignment {
+        None => return Charset::Default,
+        Some(0) | Some(2) => return Charset::Cp437,
+        Some(1) | Some(3) | Some(899) => return Charset::Latin1,
+        Some(34) => return Charset::Utf32Be,
+        Some(35) => return Charset::Utf32Le,
+        Some(4) => ISO_8859_2,
+        Some(5) => ISO_8859_3,
+        Some(6) => ISO_8859_4,
+        Some(7) => ISO_8859_5,
+        Some(8) => ISO_8859_6,
+        Some(9) => ISO_8859_7,
+        Some(10) => ISO_8859_8,
+        /* ISO-8859-9 and ISO-8859-11 are only available as the
+         * Windows supersets. */
+        Some(11) => WINDOWS_1254,
+        Some(12) => ISO_8859_10,
+        Some(13) => WINDOWS_874,
+        Some(15) => ISO_8859_13,
+        Some(16) => ISO_8859_14,
+        Some(17) => ISO_8859_15,
+        Some(18) => ISO_8859_16,
+        Some(20) => SHIFT_JIS,
+        Some(21) => WINDOWS_1250,
+        Some(22) => WINDOWS_1251,
+        Some(23) => WINDOWS_1252,
+        Some(24) => WINDOWS_1256,
+        Some(25) => UTF_16BE,
+        Some(26) | Some(27) | Some(170) => UTF_8,
+        Some(28) => BIG5,
+        Some(29) | Some(31) => GBK,
+        Some(30) => EUC_KR,
+        Some(32) => GB18030,
+        Some(33) => UTF_16LE,
+        /* Unknown assignments fall back to the default of the spec. */
+        Some(_) => return Charset::Latin1,
+    };
+
+    Charset::Other(encoding)
+}
+
+fn decode_utf32(bytes: &[u8], big_endian: bool, text: &mut String) -> bool {
+    let chunks = bytes.chunks_exact(4);
+    let truncated = !chunks.remainder().is_empty();
+    let mut lossy = truncated;
+
+    for chunk in chunks {
+        let chunk = [chunk[0], chunk[1], chunk[2], chunk[3]];
+        let value = if big_endian {
+            u32::from_be_bytes(chunk)
+        } else {
+            u32::from_le_bytes(chunk)
+        };
+
+        match std::char::from_u32(value) {
+            Some(c) => text.push(c),
+            None => {
+                text.push(std::char::REPLACEMENT_CHARACTER);
+                lossy = true;
+            }
+        }
+    }
+    if truncated {
+        text.push(std::char::REPLACEMENT_CHARACTER);
+    }
+
+    lossy
+}
+
+fn decode_with(encoding: &'static Encoding, bytes: &[u8], text: &mut String) -> bool {
+    let (decoded, lossy) = encoding.decode_without_bom_handling(bytes);
+    text.push_str(&decoded);
+    lossy
+}
+
+/// Decode `bytes` in the given character set, appending to `text`.
+/// Returns true if replacement characters were inserted.
+fn decode_bytes(charset: Charset, bytes: &[u8], text: &mut String) -> bool {
+    match charset {
+        Charset::Latin1 => {
+            text.extend(bytes.iter().map(|b| *b as char));
+            false
+        }
+        Charset::Cp437 => {
+            text.extend(bytes.iter().map(|b| match *b {
+                0..=0x7f => *b as char,
+                _ => CP437[*b as usize - 0x80],
+            }));
+            false
+        }
+        Charset::Utf32Be => decode_utf32(bytes, true, text),
+        Charset::Utf32Le => decode_utf32(bytes, false, text),
+        Charset::Other(encoding) => decode_with(encoding, bytes, text),
+        /* Without an ECI, the spec defaults to ISO-8859-1. Many encoders
+         * emit UTF-8 without saying so, so valid UTF-8 is taken as such. */
+        Charset::Default => match std::str::from_utf8(bytes) {
+            Ok(s) => {
+                text.push_str(s);
+                false
+            }
+            Err(_) => decode_bytes(Charset::Latin1, bytes, text),
+        },
+    }
+}
+
+impl Data {
+    /// Convert the payload into text.
+    ///
+    /// Each segment is converted according to its mode: Kanji segments
+    /// are Shift JIS, and byte segments use the character set of the ECI
+    /// in effect. Without an ECI, byte segments are taken as UTF-8 if
+    /// they are valid UTF-8, and as ISO-8859-1 otherwise.
+    ///
+    /// Data that is invalid in its character set is replaced by U+FFFD,
+    /// which is reported by `Text::lossy`.
+    pub fn text(&self) -> Text {
+        let mut text = String::new();
+        let mut lossy = false;
+
+        /* Consecutive segments sharing a character set are decoded
+         * together, as multi-byte characters may span them. */
+        let mut run: Vec<u8> = Vec::new();
+        let mut run_eci: Option<Option<u32>> = None;
+
+        for segment in &self.segments {
+            let eci = segment_charset(segment);
+            if run_eci != Some(eci) {
+                if let Some(eci) = run_eci {
+                    lossy |= decode_run(eci, &run, &mut text);
+                }
+                run.clear();
+                run_eci = Some(eci);
+            }
+            run.extend_from_slice(&segment.data);
+        }
+        if let Some(eci) = run_eci {
+            lossy |= decode_run(eci, &run, &mut text);
+        }
+
+        Text { text, lossy }
+    }
+}
+
+/// The ECI assignment a segment is decoded with. Numeric and
+/// alphanumeric data is ASCII, which all supported character sets except
+/// the UTF-16 and UTF-32 ones agree on.
+fn segment_charset(segment: &Segment) -> Option<u32> {
+    match segment.mode {
+        DataType::Kanji => Some(20),
+        DataType::Byte => segment.eci_assignment,
+        _ => match segment.eci_assignment {
+            Some(25) | Some(33) | Some(34) | Some(35) => Some(27),
+            eci => eci,
+        },
+    }
+}
+
+fn decode_run(eci: Option<u32>, bytes: &[u8], text: &mut String) -> bool {
+    decode_bytes(charset(eci), bytes, text)
+}
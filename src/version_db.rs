@@ -1,6 +1,12 @@
+use crate::quirc::EccLevel;
+
 pub const VERSION_MIN: usize = 1;
 pub const VERSION_MAX: usize = 40;
 
+/// Micro QR-code versions M1 to M4.
+pub const MICRO_VERSION_MIN: usize = 1;
+pub const MICRO_VERSION_MAX: usize = 4;
+
 /// QR-code version information database
 #[derive(Debug, Clone, Default)]
 pub struct RsParams {
@@ -238,7 +244,7 @@ pub static VERSION_DB: [VersionInfo; 41] = [
     },
     VersionInfo {
         data_bytes: 1156,
-        apat: [6, 28, 50, 72, 92, 0, 0],
+        apat: [6, 28, 50, 72, 94, 0, 0],
         ecc: [
             RsParams::new(68, 42, 17),
             RsParams::new(144, 116, 4),
@@ -437,3 +443,436 @@ pub static VERSION_DB: [VersionInfo; 41] = [
         ],
     },
 ];
+
+/// The cells of the copy of the format information around the top left
+/// finder pattern, as (x, y), from the least significant bit.
+pub(crate) static FORMAT_CELLS: [(i32, i32); 15] = [
+    (8, 0),
+    (8, 1),
+    (8, 2),
+    (8, 3),
+    (8, 4),
+    (8, 5),
+    (8, 7),
+    (8, 8),
+    (7, 8),
+    (5, 8),
+    (4, 8),
+    (3, 8),
+    (2, 8),
+    (1, 8),
+    (0, 8),
+];
+
+/// The smallest version which carries version information blocks.
+pub const VERSION_INFO_MIN: usize = 7;
+
+/// Compute the 18-bit version information codeword for a version: the
+/// version number followed by 12 bits of BCH(18,6) check bits.
+pub const fn version_info_bits(version: usize) -> u32 {
+    let data = (version as u32 & 0x3f) << 12;
+    let mut rem = data;
+    let mut i = 17;
+    while i >= 12 {
+        if rem & (1 << i) != 0 {
+            rem ^= 0x1f25 << (i - 12);
+        }
+        i -= 1;
+    }
+
+    data | rem
+}
+
+/// Correct a version information codeword read from a grid, returning
+/// the version it encodes. The code has a minimum distance of 8, so up
+/// to 3 bit errors can be corrected.
+pub fn correct_version_info(bits: u32) -> Option<usize> {
+    (VERSION_INFO_MIN..=VERSION_MAX)
+        .map(|version| (version, (version_info_bits(version) ^ bits).count_ones()))
+        .filter(|(_, distance)| *distance <= 3)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(version, _)| version)
+}
+
+/// Micro QR-code symbol information, as selected by the symbol number in
+/// the format information.
+#[derive(Debug, Clone)]
+pub struct MicroSymbolInfo {
+    /// The version, from 1 to 4 for M1 to M4.
+    pub version: usize,
+    /// The error correction level. M1 only detects errors, and is
+    /// reported as `L`.
+    pub ecc_level: EccLevel,
+    /// The number of data bits. In M1 and M3, the last data codeword
+    /// only holds 4 bits.
+    pub data_bits: i32,
+    /// The single Reed-Solomon block.
+    pub ecc: RsParams,
+}
+
+impl MicroSymbolInfo {
+    pub const fn new(version: usize, ecc_level: EccLevel, data_bits: i32, bs: i32) -> Self {
+        Self {
+            version,
+            ecc_level,
+            data_bits,
+            ecc: RsParams::new(bs, (data_bits + 7) / 8, 1),
+        }
+    }
+}
+
+pub static MICRO_SYMBOL_DB: [MicroSymbolInfo; 8] = [
+    MicroSymbolInfo::new(1, EccLevel::L, 20, 5),
+    MicroSymbolInfo::new(2, EccLevel::L, 40, 10),
+    MicroSymbolInfo::new(2, EccLevel::M, 32, 10),
+    MicroSymbolInfo::new(3, EccLevel::L, 84, 17),
+    MicroSymbolInfo::new(3, EccLevel::M, 68, 17),
+    MicroSymbolInfo::new(4, EccLevel::L, 128, 24),
+    MicroSymbolInfo::new(4, EccLevel::M, 112, 24),
+    MicroSymbolInfo::new(4, EccLevel::Q, 80, 24),
+];
+
+/// rMQR versions R7x43 to R17x139.
+pub const RMQR_VERSION_MIN: usize = 1;
+pub const RMQR_VERSION_MAX: usize = 32;
+
+/// rMQR version information. Versions are numbered from 1, in order of
+/// height and then width, so that `RMQR_VERSION_DB[version - 1]` is the
+/// entry for a version.
+#[derive(Debug, Clone)]
+pub struct RmqrVersionInfo {
+    /// The number of rows.
+    pub height: i32,
+    /// The number of columns.
+    pub width: i32,
+    /// The total number of data and ECC codewords.
+    pub data_bytes: i32,
+    /// Columns of the alignment patterns, followed by zeros.
+    pub apat: [i32; 4],
+    /// Error correction for the levels M and H. Large blocks hold one
+    /// more data codeword, as for QR-codes.
+    pub ecc: [RsParams; 2],
+    /// Character count bits for numeric, alphanumeric, byte and Kanji
+    /// segments.
+    pub count_bits: [i32; 4],
+}
+
+impl RmqrVersionInfo {
+    pub const fn new(
+        height: i32,
+        width: i32,
+        data_bytes: i32,
+        apat: [i32; 4],
+        ecc_m: RsParams,
+        ecc_h: RsParams,
+        count_bits: [i32; 4],
+    ) -> Self {
+        Self {
+            height,
+            width,
+            data_bytes,
+            apat,
+            ecc: [ecc_m, ecc_h],
+            count_bits,
+        }
+    }
+}
+
+pub static RMQR_VERSION_DB: [RmqrVersionInfo; 32] = [
+    RmqrVersionInfo::new(
+        7,
+        43,
+        13,
+        [21, 0, 0, 0],
+        RsParams::new(13, 6, 1),
+        RsParams::new(13, 3, 1),
+        [4, 3, 3, 2],
+    ),
+    RmqrVersionInfo::new(
+        7,
+        59,
+        21,
+        [19, 39, 0, 0],
+        RsParams::new(21, 12, 1),
+        RsParams::new(21, 7, 1),
+        [5, 5, 4, 3],
+    ),
+    RmqrVersionInfo::new(
+        7,
+        77,
+        32,
+        [25, 51, 0, 0],
+        RsParams::new(32, 20, 1),
+        RsParams::new(32, 10, 1),
+        [6, 5, 5, 4],
+    ),
+    RmqrVersionInfo::new(
+        7,
+        99,
+        44,
+        [23, 49, 75, 0],
+        RsParams::new(44, 28, 1),
+        RsParams::new(44, 14, 1),
+        [7, 6, 5, 5],
+    ),
+    RmqrVersionInfo::new(
+        7,
+        139,
+        68,
+        [27, 55, 83, 111],
+        RsParams::new(68, 44, 1),
+        RsParams::new(34, 12, 2),
+        [7, 6, 6, 5],
+    ),
+    RmqrVersionInfo::new(
+        9,
+        43,
+        21,
+        [21, 0, 0, 0],
+        RsParams::new(21, 12, 1),
+        RsParams::new(21, 7, 1),
+        [5, 5, 4, 3],
+    ),
+    RmqrVersionInfo::new(
+        9,
+        59,
+        33,
+        [19, 39, 0, 0],
+        RsParams::new(33, 21, 1),
+        RsParams::new(33, 11, 1),
+        [6, 5, 5, 4],
+    ),
+    RmqrVersionInfo::new(
+        9,
+        77,
+        49,
+        [25, 51, 0, 0],
+        RsParams::new(49, 31, 1),
+        RsParams::new(24, 8, 1),
+        [7, 6, 5, 5],
+    ),
+    RmqrVersionInfo::new(
+        9,
+        99,
+        66,
+        [23, 49, 75, 0],
+        RsParams::new(66, 42, 1),
+        RsParams::new(33, 11, 2),
+        [7, 6, 6, 5],
+    ),
+    RmqrVersionInfo::new(
+        9,
+        139,
+        99,
+        [27, 55, 83, 111],
+        RsParams::new(49, 31, 1),
+        RsParams::new(33, 11, 3),
+        [8, 7, 6, 6],
+    ),
+    RmqrVersionInfo::new(
+        11,
+        27,
+        15,
+        [0, 0, 0, 0],
+        RsParams::new(15, 7, 1),
+        RsParams::new(15, 5, 1),
+        [4, 4, 3, 2],
+    ),
+    RmqrVersionInfo::new(
+        11,
+        43,
+        31,
+        [21, 0, 0, 0],
+        RsParams::new(31, 19, 1),
+        RsParams::new(31, 11, 1),
+        [6, 5, 5, 4],
+    ),
+    RmqrVersionInfo::new(
+        11,
+        59,
+        47,
+        [19, 39, 0, 0],
+        RsParams::new(47, 31, 1),
+        RsParams::new(23, 7, 1),
+        [7, 6, 5, 5],
+    ),
+    RmqrVersionInfo::new(
+        11,
+        77,
+        67,
+        [25, 51, 0, 0],
+        RsParams::new(67, 43, 1),
+        RsParams::new(33, 11, 1),
+        [7, 6, 6, 5],
+    ),
+    RmqrVersionInfo::new(
+        11,
+        99,
+        89,
+        [23, 49, 75, 0],
+        RsParams::new(44, 28, 1),
+        RsParams::new(44, 14, 1),
+        [8, 7, 6, 6],
+    ),
+    RmqrVersionInfo::new(
+        11,
+        139,
+        132,
+        [27, 55, 83, 111],
+        RsParams::new(66, 42, 2),
+        RsParams::new(44, 14, 3),
+        [8, 7, 7, 6],
+    ),
+    RmqrVersionInfo::new(
+        13,
+        27,
+        21,
+        [0, 0, 0, 0],
+        RsParams::new(21, 12, 1),
+        RsParams::new(21, 7, 1),
+        [5, 5, 4, 3],
+    ),
+    RmqrVersionInfo::new(
+        13,
+        43,
+        41,
+        [21, 0, 0, 0],
+        RsParams::new(41, 27, 1),
+        RsParams::new(41, 13, 1),
+        [6, 6, 5, 5],
+    ),
+    RmqrVersionInfo::new(
+        13,
+        59,
+        60,
+        [19, 39, 0, 0],
+        RsParams::new(60, 38, 1),
+        RsParams::new(30, 10, 2),
+        [7, 6, 6, 5],
+    ),
+    RmqrVersionInfo::new(
+        13,
+        77,
+        85,
+        [25, 51, 0, 0],
+        RsParams::new(42, 26, 1),
+        RsParams::new(42, 14, 1),
+        [7, 7, 6, 6],
+    ),
+    RmqrVersionInfo::new(
+        13,
+        99,
+        113,
+        [23, 49, 75, 0],
+        RsParams::new(56, 36, 1),
+        RsParams::new(37, 11, 1),
+        [8, 7, 7, 6],
+    ),
+    RmqrVersionInfo::new(
+        13,
+        139,
+        166,
+        [27, 55, 83, 111],
+        RsParams::new(55, 35, 2),
+        RsParams::new(41, 13, 2),
+        [8, 8, 7, 7],
+    ),
+    RmqrVersionInfo::new(
+        15,
+        43,
+        51,
+        [21, 0, 0, 0],
+        RsParams::new(51, 33, 1),
+        RsParams::new(25, 7, 1),
+        [7, 6, 6, 5],
+    ),
+    RmqrVersionInfo::new(
+        15,
+        59,
+        74,
+        [19, 39, 0, 0],
+        RsParams::new(74, 48, 1),
+        RsParams::new(37, 13, 2),
+        [7, 7, 6, 5],
+    ),
+    RmqrVersionInfo::new(
+        15,
+        77,
+        103,
+        [25, 51, 0, 0],
+        RsParams::new(51, 33, 1),
+        RsParams::new(34, 10, 2),
+        [8, 7, 7, 6],
+    ),
+    RmqrVersionInfo::new(
+        15,
+        99,
+        136,
+        [23, 49, 75, 0],
+        RsParams::new(68, 44, 2),
+        RsParams::new(34, 12, 4),
+        [8, 7, 7, 6],
+    ),
+    RmqrVersionInfo::new(
+        15,
+        139,
+        199,
+        [27, 55, 83, 111],
+        RsParams::new(66, 42, 2),
+        RsParams::new(39, 13, 1),
+        [9, 8, 7, 7],
+    ),
+    RmqrVersionInfo::new(
+        17,
+        43,
+        61,
+        [21, 0, 0, 0],
+        RsParams::new(61, 39, 1),
+        RsParams::new(30, 10, 1),
+        [7, 6, 6, 5],
+    ),
+    RmqrVersionInfo::new(
+        17,
+        59,
+        88,
+        [19, 39, 0, 0],
+        RsParams::new(44, 28, 2),
+        RsParams::new(44, 14, 2),
+        [8, 7, 6, 6],
+    ),
+    RmqrVersionInfo::new(
+        17,
+        77,
+        122,
+        [25, 51, 0, 0],
+        RsParams::new(61, 39, 2),
+        RsParams::new(40, 12, 1),
+        [8, 7, 7, 6],
+    ),
+    RmqrVersionInfo::new(
+        17,
+        99,
+        160,
+        [23, 49, 75, 0],
+        RsParams::new(53, 33, 2),
+        RsParams::new(40, 14, 4),
+        [8, 8, 7, 6],
+    ),
+    RmqrVersionInfo::new(
+        17,
+        139,
+        232,
+        [27, 55, 83, 111],
+        RsParams::new(58, 38, 4),
+        RsParams::new(38, 12, 2),
+        [9, 8, 8, 7],
+    ),
+];
+
+/// Find the rMQR version with the given number of rows and columns.
+pub fn rmqr_version(height: i32, width: i32) -> Option<usize> {
+    RMQR_VERSION_DB
+        .iter()
+        .position(|info| info.height == height && info.width == width)
+        .map(|i| i + RMQR_VERSION_MIN)
+}
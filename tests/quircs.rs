@@ -53,7 +53,18 @@ fn two_qr_codes_small(ext: &str) {
     assert_eq!(data.mask, 1);
     assert_eq!(data.data_type, Some(quircs::DataType::Byte));
     assert_eq!(data.eci, Some(quircs::Eci::Utf8));
+    assert_eq!(data.eci_assignment, Some(26));
     assert_eq!(data.payload, b"Hello");
+    assert_eq!(
+        data.segments,
+        &[quircs::Segment {
+            mode: quircs::DataType::Byte,
+            eci: Some(quircs::Eci::Utf8),
+            eci_assignment: Some(26),
+            count: 5,
+            data: b"Hello".to_vec(),
+        }]
+    );
 
     let data = res[1].decode().unwrap();
     assert_eq!(data.version, 1);
@@ -61,6 +72,7 @@ fn two_qr_codes_small(ext: &str) {
     assert_eq!(data.mask, 3);
     assert_eq!(data.data_type, Some(quircs::DataType::Byte));
     assert_eq!(data.eci, Some(quircs::Eci::Utf8));
+    assert_eq!(data.eci_assignment, Some(26));
     assert_eq!(data.payload, b"World");
 }
 
@@ -92,6 +104,7 @@ fn two_qr_codes_large(ext: &str) {
     assert_eq!(data.mask, 2);
     assert_eq!(data.data_type, Some(quircs::DataType::Byte));
     assert_eq!(data.eci, None);
+    assert_eq!(data.eci_assignment, None);
     assert_eq!(data.payload, b"from javascript");
 
     let data = res[1].decode().unwrap();
@@ -100,6 +113,7 @@ fn two_qr_codes_large(ext: &str) {
     assert_eq!(data.mask, 2);
     assert_eq!(data.data_type, Some(quircs::DataType::Byte));
     assert_eq!(data.eci, None);
+    assert_eq!(data.eci_assignment, None);
     assert_eq!(data.payload, b"here comes qr!");
 }
 
@@ -171,7 +185,1248 @@ fn generated_png() {
                     &data.payload,
                     mode_to_data.get(mode).expect("missing data for mode")
                 );
+                assert_eq!(data.segments.len(), 1);
+                assert_eq!(data.segments[0].mode, *mode);
+            }
+        }
+    }
+}
+
+fn decode_all(q: &mut Quirc, width: usize, height: usize, image: &[u8]) -> Vec<Vec<u8>> {
+    q.identify(width, height, image)
+        .map(|code| code.unwrap().decode().unwrap().payload)
+        .collect()
+}
+
+#[test]
+fn inverted_qr_codes() {
+    use quircs::Inversion;
+
+    let image = image::open("./tests/data/Hello+World.png")
+        .unwrap()
+        .into_luma8();
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let inverted: Vec<u8> = image.iter().map(|v| 255 - v).collect();
+
+    let mut q = Quirc::default();
+    assert!(decode_all(&mut q, width, height, &inverted).is_empty());
+
+    q.inversion = Inversion::Inverted;
+    assert_eq!(
+        decode_all(&mut q, width, height, &inverted),
+        vec![b"Hello".to_vec(), b"World".to_vec()]
+    );
+    assert!(decode_all(&mut q, width, height, &image).is_empty());
+
+    // Both polarities side by side.
+    let mut combined = Vec::with_capacity(width * height * 2);
+    for (row, inverted_row) in image.chunks(width).zip(inverted.chunks(width)) {
+        combined.extend_from_slice(row);
+        combined.extend_from_slice(inverted_row);
+    }
+
+    q.inversion = Inversion::Both;
+    let mut payloads = decode_all(&mut q, width * 2, height, &combined);
+    payloads.sort();
+    assert_eq!(
+        payloads,
+        vec![
+            b"Hello".to_vec(),
+            b"Hello".to_vec(),
+            b"World".to_vec(),
+            b"World".to_vec()
+        ]
+    );
+
+    // The regions of capstones from both passes are still in place.
+    assert!(q.capstones.iter().any(|cap| cap.inverted));
+    assert!(q.capstones.iter().any(|cap| !cap.inverted));
+    for (index, cap) in q.capstones.iter().enumerate() {
+        assert_eq!(q.regions[cap.ring as usize].capstone, index as i32);
+        assert_eq!(q.regions[cap.stone as usize].capstone, index as i32);
+    }
+
+    assert_eq!(decode_all(&mut q, width, height, &image).len(), 2);
+}
+
+#[test]
+fn inverted_duplicates() {
+    use qrcode::{EcLevel, QrCode, Version};
+    use quircs::Inversion;
+
+    // A light-on-dark code printed over the middle of a larger dark-on-light
+    // one, which has enough error correction to be read regardless.
+    let outer = QrCode::with_version(b"outer", Version::Normal(6), EcLevel::H).unwrap();
+    let inner = QrCode::with_version(b"inner", Version::Normal(1), EcLevel::L).unwrap();
+    let mut image = render_qrcode(&outer, 8, 4, None);
+    let inner = render_qrcode(&inner, 4, 2, None);
+    let offset = (image.width() - inner.width()) / 2;
+    for (x, y, p) in inner.enumerate_pixels() {
+        image.put_pixel(offset + x, offset + y, image::Luma([255 - p[0]]));
+    }
+    let (width, height) = (image.width() as usize, image.height() as usize);
+
+    let mut q = Quirc::default();
+    assert_eq!(
+        decode_all(&mut q, width, height, &image),
+        vec![b"outer".to_vec()]
+    );
+
+    q.inversion = Inversion::Inverted;
+    assert_eq!(
+        decode_all(&mut q, width, height, &image),
+        vec![b"inner".to_vec()]
+    );
+
+    // Both codes are found, but the inner one lies within the outer one
+    // and is dropped.
+    q.inversion = Inversion::Both;
+    assert_eq!(
+        decode_all(&mut q, width, height, &image),
+        vec![b"outer".to_vec()]
+    );
+    assert_eq!(q.capstones.iter().filter(|cap| cap.inverted).count(), 3);
+    assert!(q.capstones.iter().all(|cap| cap.qr_grid < 1));
+}
+
+#[test]
+fn mirrored_qr_codes() {
+    let image = image::open("./tests/data/Hello+World.png")
+        .unwrap()
+        .into_luma8();
+    let mirrored = image::imageops::flip_horizontal(&image);
+
+    let mut q = Quirc::default();
+    for (image, is_mirrored) in [(image, false), (mirrored, true)] {
+        let data: Vec<_> = q
+            .identify(image.width() as usize, image.height() as usize, &image)
+            .map(|code| code.unwrap().decode().unwrap())
+            .collect();
+        assert_eq!(data.len(), 2);
+
+        let mut payloads: Vec<_> = data.iter().map(|d| d.payload.clone()).collect();
+        payloads.sort();
+        assert_eq!(payloads, vec![b"Hello".to_vec(), b"World".to_vec()]);
+        assert!(data.iter().all(|d| d.mirrored == is_mirrored));
+    }
+}
+
+#[test]
+fn adaptive_thresholding() {
+    use quircs::AdaptiveThreshold;
+    use std::sync::Arc;
+
+    let image = image::open("./tests/data/Hello+World.png")
+        .unwrap()
+        .into_luma8();
+    let (width, height) = (image.width() as usize, image.height() as usize);
+
+    // Cast a shadow which gets darker towards the left edge.
+    let shadowed: Vec<u8> = image
+        .enumerate_pixels()
+        .map(|(x, _, p)| {
+            let light = 0.15 + 0.85 * (x as f64 / width as f64);
+            (p[0] as f64 * light) as u8
+        })
+        .collect();
+
+    let mut q = Quirc::default();
+    assert!(decode_all(&mut q, width, height, &shadowed).len() < 2);
+
+    q.binarizer = Arc::new(AdaptiveThreshold::default());
+    let mut payloads = decode_all(&mut q, width, height, &shadowed);
+    payloads.sort();
+    assert_eq!(payloads, vec![b"Hello".to_vec(), b"World".to_vec()]);
+}
+
+#[test]
+fn custom_binarizers() {
+    use quircs::{Binarizer, FixedThreshold, Pixel};
+    use std::sync::Arc;
+
+    /// Treats everything below mid-gray as dark, counting invocations.
+    #[derive(Debug, Default)]
+    struct Counting(std::sync::atomic::AtomicUsize);
+
+    impl Binarizer for Counting {
+        fn binarize(&self, image: &[u8], width: usize, height: usize, pixels: &mut [Pixel]) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            FixedThreshold(128).binarize(image, width, height, pixels);
+        }
+    }
+
+    let image = image::open("./tests/data/Hello+World.png")
+        .unwrap()
+        .into_luma8();
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let expected = vec![b"Hello".to_vec(), b"World".to_vec()];
+
+    let mut q = Quirc::default();
+    let counting = Arc::new(Counting::default());
+    q.binarizer = counting.clone();
+    assert_eq!(decode_all(&mut q, width, height, &image), expected);
+    assert_eq!(counting.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    // Both polarities are searched from a single binarization.
+    q.inversion = quircs::Inversion::Both;
+    assert_eq!(decode_all(&mut q, width, height, &image), expected);
+    assert_eq!(counting.0.load(std::sync::atomic::Ordering::SeqCst), 2);
+    q.inversion = quircs::Inversion::Normal;
+
+    q.binarizer = Arc::new(FixedThreshold(0));
+    assert!(decode_all(&mut q, width, height, &image).is_empty());
+
+    let binary: Vec<u8> = image.iter().map(|v| (*v < 128) as u8).collect();
+    let res: Vec<_> = q
+        .identify_binary(width, height, &binary)
+        .map(|code| code.unwrap().decode().unwrap().payload)
+        .collect();
+    assert_eq!(res, expected);
+
+    /// Marks dark pixels with all bits set, rather than with 1.
+    #[derive(Debug)]
+    struct Saturating;
+
+    impl Binarizer for Saturating {
+        fn binarize(&self, image: &[u8], _width: usize, _height: usize, pixels: &mut [Pixel]) {
+            for (value, dest) in image.iter().zip(pixels.iter_mut()) {
+                *dest = if *value < 128 { Pixel::MAX } else { 0 };
             }
         }
     }
+
+    q.binarizer = Arc::new(Saturating);
+    q.inversion = quircs::Inversion::Both;
+    assert_eq!(decode_all(&mut q, width, height, &image), expected);
+}
+
+#[test]
+fn structured_append_assembly() {
+    use quircs::{Data, StructuredAppend, StructuredAppendAssembler, StructuredAppendError};
+
+    let message = b"Hello structured world";
+    let parity = message.iter().fold(0, |p, b| p ^ b);
+    let symbol = |index: u8, total: u8, payload: &[u8]| Data {
+        payload: payload.to_vec(),
+        structured_append: Some(StructuredAppend {
+            index,
+            total,
+            parity,
+        }),
+        ..Default::default()
+    };
+
+    let mut assembler = StructuredAppendAssembler::new();
+    assert!(matches!(
+        assembler.push(&Data::default()),
+        Err(StructuredAppendError::NotStructuredAppend)
+    ));
+
+    assert_eq!(assembler.push(&symbol(2, 3, b" world")).unwrap(), None);
+    assert_eq!(assembler.push(&symbol(0, 3, b"Hello")).unwrap(), None);
+    assert_eq!(assembler.push(&symbol(0, 3, b"Hello")).unwrap(), None);
+    assert!(matches!(
+        assembler.push(&symbol(0, 3, b"Jello")),
+        Err(StructuredAppendError::Conflict(0))
+    ));
+    assert_eq!(assembler.pending(), 1);
+    assert_eq!(
+        assembler.push(&symbol(1, 3, b" structured")).unwrap(),
+        Some(message.to_vec())
+    );
+    assert_eq!(assembler.pending(), 0);
+
+    assembler.push(&symbol(0, 2, b"Hello")).unwrap();
+    assert!(matches!(
+        assembler.push(&symbol(1, 2, b" there")),
+        Err(StructuredAppendError::ParityMismatch)
+    ));
+}
+
+#[test]
+fn gs1_element_strings() {
+    use quircs::{parse_gs1, Gs1Error};
+
+    let elements = parse_gs1(b"01095011010209171719050810ABCD1234\x1d2110").unwrap();
+    assert_eq!(
+        elements,
+        vec![
+            ("01".to_string(), "09501101020917".to_string()),
+            ("17".to_string(), "190508".to_string()),
+            ("10".to_string(), "ABCD1234".to_string()),
+            ("21".to_string(), "10".to_string()),
+        ]
+    );
+
+    let elements = parse_gs1(b"00106141411234567897\x1d").unwrap();
+    assert_eq!(
+        elements,
+        vec![("00".to_string(), "106141411234567897".to_string())]
+    );
+
+    let elements = parse_gs1(b"04012345678901234510ABC").unwrap();
+    assert_eq!(
+        elements,
+        vec![
+            ("04".to_string(), "0123456789012345".to_string()),
+            ("10".to_string(), "ABC".to_string()),
+        ]
+    );
+
+    assert!(matches!(
+        parse_gs1(b"0109501101020918"),
+        Err(Gs1Error::CheckDigit(ai)) if ai == "01"
+    ));
+    assert!(matches!(parse_gs1(b"01095011"), Err(Gs1Error::Truncated)));
+    assert!(matches!(
+        parse_gs1(b"171905\x1d10ABCD"),
+        Err(Gs1Error::InvalidValue(ai)) if ai == "17"
+    ));
+    assert!(matches!(
+        parse_gs1(b"ABC"),
+        Err(Gs1Error::InvalidApplicationIdentifier(_))
+    ));
+}
+
+#[cfg(feature = "text")]
+#[test]
+fn text_decoding() {
+    use quircs::{Data, DataType, Segment};
+
+    let segment = |mode: DataType, eci_assignment: Option<u32>, data: &[u8]| Segment {
+        mode,
+        eci: None,
+        eci_assignment,
+        count: data.len(),
+        data: data.to_vec(),
+    };
+    let text = |segments: Vec<Segment>| {
+        Data {
+            segments,
+            ..Default::default()
+        }
+        .text()
+    };
+
+    /* Kanji is Shift JIS, byte data without an ECI defaults to ISO-8859-1 */
+    let t = text(vec![
+        segment(DataType::Kanji, None, &[0x93, 0x5f, 0xe4, 0xaa]),
+        segment(DataType::Byte, None, b"caf\xe9"),
+        segment(DataType::Numeric, None, b"42"),
+    ]);
+    assert_eq!(t.text, "点茗café42");
+    assert!(!t.lossy);
+
+    /* UTF-8 split across segments, and other ECIs */
+    let t = text(vec![
+        segment(DataType::Byte, Some(26), b"\xe2\x82"),
+        segment(DataType::Byte, Some(26), b"\xac"),
+        segment(DataType::Byte, Some(2), b"\x9c"),
+        segment(DataType::Byte, Some(7), b"\xbf"),
+    ]);
+    assert_eq!(t.text, "€£П");
+    assert!(!t.lossy);
+
+    let t = text(vec![segment(DataType::Byte, Some(26), b"ok\xff")]);
+    assert_eq!(t.text, "ok\u{fffd}");
+    assert!(t.lossy);
+}
+
+#[test]
+fn version_information() {
+    use quircs::{correct_version_info, version_info_bits};
+
+    assert_eq!(version_info_bits(7), 0x07c94);
+    assert_eq!(version_info_bits(40), 0x28c69);
+    assert_eq!(
+        correct_version_info(0x07c94 ^ 0b100_0000_0001_0001),
+        Some(7)
+    );
+    assert_eq!(correct_version_info(0x28c69 ^ 0b1111), None);
+
+    /* Darken light modules of both timing patterns, so that they are
+     * miscounted. The version information restores the grid size.
+     */
+    let mut image = image::open("tests/data/generated/version=10,level=M,mode=BYTE.png")
+        .expect("failed to open image")
+        .into_luma8();
+    let (origin, module) = (12, 3);
+    for cell in &[9, 11, 13] {
+        for (cx, cy) in &[(*cell, 6), (6, *cell)] {
+            for y in 0..module {
+                for x in 0..module {
+                    image.put_pixel(
+                        origin + cx * module + x,
+                        origin + cy * module + y,
+                        image::Luma([0]),
+                    );
+                }
+            }
+        }
+    }
+
+    let mut q = Quirc::default();
+    let res: Vec<_> = q
+        .identify(image.width() as usize, image.height() as usize, &image)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].width, 57);
+
+    let data = res[0].decode().expect("failed to decode");
+    assert_eq!(data.version, 10);
+    assert_eq!(data.payload, b"aA1234");
+}
+
+#[test]
+fn detector_limits() {
+    use quircs::{DetectorConfig, Truncation};
+
+    /* Tile the image so that it holds 18 codes */
+    let tile = image::open("tests/data/Hello+World.png")
+        .expect("failed to open image")
+        .into_luma8();
+    let mut image = image::GrayImage::new(tile.width() * 3, tile.height() * 3);
+    for y in 0..3 {
+        for x in 0..3 {
+            let (x, y) = (x * tile.width() as i64, y * tile.height() as i64);
+            image::imageops::replace(&mut image, &tile, x, y);
+        }
+    }
+    let (width, height) = (image.width() as usize, image.height() as usize);
+
+    let mut q = Quirc::default();
+    let codes = q.identify(width, height, &image);
+    assert_eq!(codes.truncation(), Truncation::default());
+    assert_eq!(codes.count(), 18);
+
+    q.config = DetectorConfig {
+        max_grids: 8,
+        ..Default::default()
+    };
+    let codes = q.identify(width, height, &image);
+    assert!(codes.truncation().grids);
+    assert_eq!(codes.count(), 8);
+
+    q.config = DetectorConfig {
+        max_capstones: 30,
+        ..Default::default()
+    };
+    let codes = q.identify(width, height, &image);
+    assert_eq!(
+        codes.truncation(),
+        Truncation {
+            capstones: true,
+            ..Default::default()
+        }
+    );
+    assert!(codes.count() < 18);
+}
+
+/// Changes the value of a cell, given its position in the code, the
+/// position of the pixel within the cell and whether the cell is dark.
+type CellModifier<'a> = &'a dyn Fn((u32, u32), (u32, u32), bool) -> bool;
+
+/// Render a code generated by the `qrcode` crate, with a quiet zone. If
+/// given, `modify` decides the value of each pixel of the code itself.
+fn render_qrcode(
+    code: &qrcode::QrCode,
+    scale: u32,
+    quiet: u32,
+    modify: Option<CellModifier<'_>>,
+) -> image::GrayImage {
+    let width = code.width() as u32;
+    let colors = code.to_colors();
+    let side = (width + quiet * 2) * scale;
+
+    image::GrayImage::from_fn(side, side, |x, y| {
+        let (u, v) = (
+            (x / scale).wrapping_sub(quiet),
+            (y / scale).wrapping_sub(quiet),
+        );
+        if u >= width || v >= width {
+            return image::Luma([255]);
+        }
+        let mut dark = colors[(v * width + u) as usize] == qrcode::Color::Dark;
+        if let Some(modify) = modify {
+            dark = modify((u, v), (x % scale, y % scale), dark);
+        }
+        image::Luma([if dark { 0 } else { 255 }])
+    })
+}
+
+#[test]
+fn micro_qr_codes() {
+    use qrcode::{EcLevel, QrCode, Version};
+    use quircs::{CodeKind, DataType, EccLevel};
+
+    // M3-L is left out, as the qrcode crate places its last data codeword
+    // as 8 bits rather than 4.
+    let cases: &[(i16, EcLevel, &[u8], EccLevel, DataType)] = &[
+        (1, EcLevel::L, b"12345", EccLevel::L, DataType::Numeric),
+        (2, EcLevel::L, b"AC-42", EccLevel::L, DataType::Alpha),
+        (2, EcLevel::M, b"123456", EccLevel::M, DataType::Numeric),
+        (3, EcLevel::M, b"1234567", EccLevel::M, DataType::Numeric),
+        (4, EcLevel::L, b"Micro QR!", EccLevel::L, DataType::Byte),
+        (
+            4,
+            EcLevel::M,
+            &[0x93, 0x5f, 0xe4, 0xaa],
+            EccLevel::M,
+            DataType::Kanji,
+        ),
+        (4, EcLevel::Q, b"quircs", EccLevel::Q, DataType::Byte),
+    ];
+
+    let mut q = Quirc::default();
+    for (version, level, payload, ecc_level, mode) in cases {
+        let code = QrCode::with_version(payload, Version::Micro(*version), *level).unwrap();
+        let image = render_qrcode(&code, 4, 4, None);
+
+        for image in &[
+            image.clone(),
+            image::imageops::rotate90(&image),
+            image::imageops::flip_horizontal(&image),
+        ] {
+            let codes: Vec<_> = q
+                .identify(image.width() as usize, image.height() as usize, image)
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(codes.len(), 1, "M{} {:?}", version, level);
+            assert_eq!(codes[0].kind, CodeKind::MicroQr);
+
+            let data = codes[0].decode().expect("failed to decode");
+            assert_eq!(data.kind, CodeKind::MicroQr);
+            assert_eq!(data.version, *version as usize);
+            assert_eq!(data.ecc_level, *ecc_level);
+            assert_eq!(data.data_type, Some(*mode));
+            assert_eq!(&data.payload, payload);
+        }
+    }
+
+    // Micro QR-codes can be left out.
+    let code = QrCode::with_version(b"12345", Version::Micro(1), EcLevel::L).unwrap();
+    let image = render_qrcode(&code, 4, 4, None);
+    q.config = quircs::DetectorConfig {
+        micro_qr: false,
+        ..Default::default()
+    };
+    let codes = q.identify(image.width() as usize, image.height() as usize, &image);
+    assert_eq!(codes.count(), 0);
+}
+
+#[test]
+fn rmqr_codes() {
+    use quircs::{Code, CodeKind, DataType, EccLevel, Segment, RMQR_VERSION_DB};
+
+    let mut q = Quirc::default();
+    for version in 1..=RMQR_VERSION_DB.len() {
+        let info = &RMQR_VERSION_DB[version - 1];
+        for ecc_level in [EccLevel::M, EccLevel::H] {
+            let payload = version.to_string();
+            let segments = [Segment::new(DataType::Byte, payload.as_str())];
+            let code = Code::encode_rmqr(&segments, version, ecc_level).unwrap();
+            let luma = code.to_luma(4, 2);
+            let image =
+                image::GrayImage::from_raw(luma.width as u32, luma.height as u32, luma.pixels)
+                    .unwrap();
+
+            for image in &[image.clone(), image::imageops::rotate90(&image)] {
+                let codes: Vec<_> = q
+                    .identify(image.width() as usize, image.height() as usize, image)
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                assert_eq!(codes.len(), 1, "version {} {:?}", version, ecc_level);
+                assert_eq!(codes[0].kind, CodeKind::Rmqr);
+                assert_eq!((codes[0].width, codes[0].height), (info.width, info.height));
+
+                let data = codes[0].decode().expect("failed to decode");
+                assert_eq!(data.kind, CodeKind::Rmqr);
+                assert_eq!(data.version, version);
+                assert_eq!(data.ecc_level, ecc_level);
+                assert_eq!(data.data_type, Some(DataType::Byte));
+                assert_eq!(data.payload, payload.as_bytes());
+            }
+        }
+    }
+
+    // rMQR codes can be left out, along with the Micro QR-codes their
+    // left end could pass for.
+    let segments = [Segment::new(DataType::Numeric, "0123456789")];
+    let luma = Code::encode_rmqr(&segments, 1, EccLevel::M)
+        .unwrap()
+        .to_luma(4, 2);
+    q.config = quircs::DetectorConfig {
+        micro_qr: false,
+        rmqr: false,
+        ..Default::default()
+    };
+    let codes = q.identify(luma.width, luma.height, &luma.pixels);
+    assert_eq!(codes.count(), 0);
+
+    assert!(matches!(
+        Code::encode_rmqr(&segments, 33, EccLevel::M),
+        Err(quircs::EncodeError::InvalidVersion(33))
+    ));
+    assert!(matches!(
+        Code::encode_rmqr(&segments, 1, EccLevel::L),
+        Err(quircs::EncodeError::UnsupportedEccLevel(EccLevel::L))
+    ));
+    assert!(matches!(
+        Code::encode_rmqr(&[Segment::new(DataType::Byte, [0; 64])], 1, EccLevel::H),
+        Err(quircs::EncodeError::DataTooLong)
+    ));
+}
+
+#[test]
+fn erasure_decoding() {
+    use qrcode::{EcLevel, QrCode, Version};
+
+    // A code turned by 45 degrees, with its bottom right corner cut off by
+    // the edge of the image. More codewords are lost than can be corrected
+    // as errors, but few enough to be corrected as erasures.
+    let code = QrCode::with_version(b"erase", Version::Normal(1), EcLevel::H).unwrap();
+    let width = code.width() as f64;
+    let colors = code.to_colors();
+    let scale = 6.0;
+    let (sin, cos) = std::f64::consts::FRAC_PI_4.sin_cos();
+    let side = ((width + 8.0) * scale * std::f64::consts::SQRT_2) as u32;
+    let center = side as f64 / 2.0;
+    // Cells whose centres have u + v > 29.5 lie outside the image.
+    let height = (center + (29.5 - width) * scale / std::f64::consts::SQRT_2) as u32;
+
+    let image = image::GrayImage::from_fn(side, height, |x, y| {
+        let (dx, dy) = (x as f64 - center, y as f64 - center);
+        let u = (dx * cos + dy * sin) / scale + width / 2.0;
+        let v = (dy * cos - dx * sin) / scale + width / 2.0;
+        let dark = u >= 0.0
+            && v >= 0.0
+            && u < width
+            && v < width
+            && colors[v as usize * width as usize + u as usize] == qrcode::Color::Dark;
+        image::Luma([if dark { 0 } else { 255 }])
+    });
+
+    let mut q = Quirc::default();
+    let codes: Vec<_> = q
+        .identify(image.width() as usize, image.height() as usize, &image)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(codes.len(), 1);
+    assert!(codes[0].erasure_bitmap.iter().any(|b| *b != 0));
+
+    let data = codes[0].decode().expect("failed to decode");
+    assert_eq!(data.payload, b"erase");
+
+    let mut code = codes[0];
+    code.erasure_bitmap = quircs::Code::default().erasure_bitmap;
+    assert!(matches!(code.decode(), Err(quircs::DecodeError::DataEcc)));
+}
+
+#[test]
+fn cell_confidence() {
+    use qrcode::{EcLevel, QrCode, Version};
+
+    // Specks in the middle of the cells in the bottom right corner flip
+    // the values read from them. There are too many to correct as errors,
+    // but the samples around the specks show that they can't be trusted.
+    let code = QrCode::with_version(b"speck", Version::Normal(1), EcLevel::H).unwrap();
+    let scale = 6;
+    let speck = |(u, v): (u32, u32), (x, y): (u32, u32), dark: bool| {
+        dark != (u + v >= 29 && x == scale / 2 && y == scale / 2)
+    };
+    let image = render_qrcode(&code, scale, 4, Some(&speck));
+
+    let mut q = Quirc::default();
+    let codes: Vec<_> = q
+        .identify(image.width() as usize, image.height() as usize, &image)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(codes.len(), 1);
+    assert!(matches!(
+        codes[0].decode(),
+        Err(quircs::DecodeError::DataEcc)
+    ));
+
+    let (code, confidence) = q.extract_with_confidence(0).unwrap();
+    assert_eq!((confidence.width, confidence.height), (21, 21));
+    for y in 0..21 {
+        for x in 0..21 {
+            assert_eq!(confidence.get(x, y) < 128, x + y >= 29, "({}, {})", x, y);
+        }
+    }
+
+    let data = code.decode().expect("failed to decode");
+    assert_eq!(data.payload, b"speck");
+    assert!(q.extract_with_confidence(1).is_err());
+}
+
+#[test]
+fn sampling_vote() {
+    use quircs::Sampling;
+
+    // Flip one pixel in twenty. Reading only the centre of each cell, too
+    // many cells are flipped to correct.
+    let mut image = image::open("tests/data/generated/version=05,level=M,mode=BYTE.png")
+        .expect("failed to open image")
+        .into_luma8();
+    let mut seed: u32 = 1;
+    for pixel in image.pixels_mut() {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        if seed >> 16 < 0x10000 / 20 {
+            pixel.0[0] = 255 - pixel.0[0];
+        }
+    }
+
+    let mut q = Quirc::default();
+    let codes: Vec<_> = q
+        .identify(image.width() as usize, image.height() as usize, &image)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(codes.len(), 1);
+    assert!(codes[0].decode().is_err());
+
+    q.sampling = Sampling::Vote;
+    let codes: Vec<_> = q
+        .identify(image.width() as usize, image.height() as usize, &image)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(codes.len(), 1);
+    let data = codes[0].decode().expect("failed to decode");
+    assert_eq!(data.payload, b"aA1234");
+}
+
+#[test]
+fn curved_large_code() {
+    use qrcode::{EcLevel, QrCode, Version};
+
+    // The rows in the middle of the code are pushed sideways, as on a
+    // curved surface, so that no single perspective transform fits all
+    // of them.
+    let code = QrCode::with_version(b"curved", Version::Normal(25), EcLevel::L).unwrap();
+    let width = code.width() as f64;
+    let colors = code.to_colors();
+    let scale = 4.0;
+    let side = ((width + 12.0) * scale) as u32;
+
+    let image = image::GrayImage::from_fn(side, side, |x, y| {
+        let v = y as f64 / scale - 4.0;
+        let u = x as f64 / scale - 4.0 - 2.0 * (std::f64::consts::PI * v / width).sin();
+        let dark = u >= 0.0
+            && v >= 0.0
+            && u < width
+            && v < width
+            && colors[v as usize * width as usize + u as usize] == qrcode::Color::Dark;
+        image::Luma([if dark { 0 } else { 255 }])
+    });
+
+    let mut q = Quirc::default();
+    let codes: Vec<_> = q
+        .identify(image.width() as usize, image.height() as usize, &image)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(codes.len(), 1);
+    assert!(q.grids[0].mesh.is_some());
+
+    let data = codes[0].decode().expect("failed to decode");
+    assert_eq!(data.version, 25);
+    assert_eq!(data.payload, b"curved");
+}
+
+#[test]
+fn missing_capstone() {
+    use qrcode::{EcLevel, QrCode, Version};
+
+    let code = QrCode::with_version(b"two capstones", Version::Normal(3), EcLevel::M).unwrap();
+    let width = code.width() as u32;
+
+    // Cover each capstone in turn, with a blot which also hides its
+    // separator.
+    for (cx, cy) in [(0, 0), (width - 8, 0), (0, width - 8)] {
+        let blot = |(u, v): (u32, u32), _, dark: bool| {
+            dark || (cx..cx + 8).contains(&u) && (cy..cy + 8).contains(&v)
+        };
+        let image = render_qrcode(&code, 4, 4, Some(&blot));
+
+        let mut q = Quirc::default();
+        let codes: Vec<_> = q
+            .identify(image.width() as usize, image.height() as usize, &image)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(codes.len(), 1, "capstone at ({}, {})", cx, cy);
+        assert_eq!(q.capstones.iter().filter(|c| c.inferred).count(), 1);
+        let inferred = q.capstones.iter().find(|c| c.inferred).unwrap();
+        assert_eq!((inferred.ring, inferred.stone), (-1, -1));
+
+        let data = codes[0].decode().expect("failed to decode");
+        assert_eq!(data.payload, b"two capstones");
+
+        // Inferred capstones count towards the limit.
+        q.config = quircs::DetectorConfig {
+            max_capstones: q.capstones.len() - 1,
+            ..Default::default()
+        };
+        let codes = q.identify(image.width() as usize, image.height() as usize, &image);
+        assert!(codes.truncation().capstones);
+        assert_eq!(codes.count(), 0);
+    }
+}
+
+#[test]
+fn quality_grading() {
+    use qrcode::{EcLevel, QrCode, Version};
+    use quircs::Grade;
+
+    let code = QrCode::with_version(b"print quality", Version::Normal(7), EcLevel::M).unwrap();
+    let image = render_qrcode(&code, 4, 4, None);
+    let grade = |image: &image::GrayImage| {
+        let mut q = Quirc::default();
+        assert_eq!(
+            q.identify(image.width() as usize, image.height() as usize, image)
+                .count(),
+            1
+        );
+        q.grade(0, image).expect("failed to grade")
+    };
+
+    let report = grade(&image);
+    assert_eq!(report.grade, Grade::A);
+    assert_eq!(report.symbol_contrast.value, 1.0);
+    assert_eq!(report.fixed_pattern_damage.value, 0.0);
+    assert_eq!(report.unused_error_correction.value, 1.0);
+
+    // Faded print only has a low contrast.
+    let faded = image::GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        image::Luma([if image.get_pixel(x, y)[0] == 0 {
+            90
+        } else {
+            160
+        }])
+    });
+    let report = grade(&faded);
+    assert_eq!(report.symbol_contrast.grade, Grade::D);
+    assert_eq!(report.modulation.grade, Grade::A);
+    assert_eq!(report.grade, Grade::D);
+
+    // Dark blots in the data use up error correction, and leave modules
+    // on the wrong side of the threshold.
+    let blotted = image::GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let (u, v) = (x / 4, y / 4);
+        let blot = (20..32).contains(&u) && (20..32).contains(&v);
+        image::Luma([if blot { 0 } else { image.get_pixel(x, y)[0] }])
+    });
+    let report = grade(&blotted);
+    assert!(report.unused_error_correction.value < 1.0);
+    assert!(report.reflectance_margin.grade < Grade::A);
+    assert!(report.grade < Grade::A);
+
+    let q = Quirc::default();
+    assert!(q.grade(0, &image).is_err());
+
+    // The timing patterns and finder sub-pattern of rMQR codes are fixed
+    // patterns too.
+    let rmqr = |version| {
+        let segments = [quircs::Segment::new(quircs::DataType::Byte, "rMQR")];
+        let code = quircs::Code::encode_rmqr(&segments, version, quircs::EccLevel::M).unwrap();
+        let luma = code.to_luma(4, 2);
+        image::GrayImage::from_raw(luma.width as u32, luma.height as u32, luma.pixels).unwrap()
+    };
+    for version in 1..=quircs::RMQR_VERSION_DB.len() {
+        let report = grade(&rmqr(version));
+        assert_eq!(
+            report.fixed_pattern_damage.value, 0.0,
+            "version {}",
+            version
+        );
+        assert_eq!(report.grid_nonuniformity.value, 0.0);
+    }
+
+    // A light cell in the top timing pattern, past the quiet zone.
+    let image = rmqr(32);
+    let broken = image::GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let light = (x / 4, y / 4) == (2 + 12, 2);
+        image::Luma([if light { 255 } else { image.get_pixel(x, y)[0] }])
+    });
+    let report = grade(&broken);
+    assert!(report.fixed_pattern_damage.value > 0.0);
+    assert!(report.fixed_pattern_damage.grade < Grade::A);
+}
+
+#[test]
+fn error_statistics() {
+    use qrcode::{EcLevel, QrCode, Version};
+
+    let code = QrCode::with_version(b"worn print", Version::Normal(1), EcLevel::M).unwrap();
+    let decode = |flipped: &[(u32, u32)]| {
+        let flip = |cell, _, dark: bool| dark != flipped.contains(&cell);
+        let image = render_qrcode(&code, 4, 4, Some(&flip));
+
+        let mut q = Quirc::default();
+        let codes: Vec<_> = q
+            .identify(image.width() as usize, image.height() as usize, &image)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(codes.len(), 1);
+        codes[0].decode().expect("failed to decode")
+    };
+
+    let data = decode(&[]);
+    assert_eq!(data.payload, b"worn print");
+    assert_eq!(data.format_copy, 0);
+    assert_eq!(data.format_errors, 0);
+    assert_eq!(data.blocks.len(), 1);
+    assert_eq!(data.blocks[0].codewords, 26);
+    assert_eq!(data.blocks[0].ecc_codewords, 10);
+    assert_eq!(data.blocks[0].capacity, 4);
+    assert_eq!(data.blocks[0].errors, 0);
+
+    // Damage two data codewords and one bit of the first copy of the
+    // format information.
+    let data = decode(&[(20, 20), (20, 16), (8, 1)]);
+    assert_eq!(data.payload, b"worn print");
+    assert_eq!(data.format_copy, 0);
+    assert_eq!(data.format_errors, 1);
+    assert_eq!(data.blocks[0].errors, 2);
+
+    // With the first copy beyond repair, the second one is used.
+    let data = decode(&[(8, 0), (8, 1), (8, 2), (8, 3)]);
+    assert_eq!(data.payload, b"worn print");
+    assert_eq!(data.format_copy, 1);
+    assert_eq!(data.format_errors, 0);
+}
+
+#[test]
+fn mixed_block_sizes() {
+    use qrcode::{EcLevel, QrCode, Version};
+
+    // Version 5-Q has two blocks of 15 data codewords followed by two
+    // blocks of 16. Each codeword of the long blocks must be read from its
+    // own place in the interleaved codestream, so that none need
+    // correcting.
+    let code =
+        QrCode::with_version(b"short and long blocks", Version::Normal(5), EcLevel::Q).unwrap();
+    let image = render_qrcode(&code, 4, 4, None);
+
+    let mut q = Quirc::default();
+    let codes: Vec<_> = q
+        .identify(image.width() as usize, image.height() as usize, &image)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(codes.len(), 1);
+
+    let data = codes[0].decode().expect("failed to decode");
+    assert_eq!(data.payload, b"short and long blocks");
+    let lengths: Vec<_> = data
+        .blocks
+        .iter()
+        .map(|b| b.codewords - b.ecc_codewords)
+        .collect();
+    assert_eq!(lengths, [15, 15, 16, 16]);
+    assert!(data.blocks.iter().all(|b| b.errors == 0 && b.erasures == 0));
+}
+
+#[test]
+fn encode_round_trip() {
+    use quircs::{Code, DataType, EccLevel, Segment};
+
+    let levels = [EccLevel::L, EccLevel::M, EccLevel::Q, EccLevel::H];
+    let payloads: Vec<Vec<u8>> = vec![
+        b"0123456789".to_vec(),
+        b"HELLO WORLD $%*+-./:".to_vec(),
+        b"Hello, world!".to_vec(),
+        (0..2000).map(|i| b'0' + (i % 10) as u8).collect(),
+        (0..1200).map(|i| b"ABC123 "[i % 7]).collect(),
+        (0..=255).cycle().take(1000).collect(),
+    ];
+
+    for payload in &payloads {
+        for level in &levels {
+            let code = match Code::encode(payload, *level) {
+                Ok(code) => code,
+                Err(quircs::EncodeError::DataTooLong) => continue,
+                Err(err) => panic!("{}", err),
+            };
+            let data = code.decode().expect("failed to decode");
+            assert_eq!(&data.payload, payload);
+            assert_eq!(data.ecc_level, *level);
+            assert_eq!(data.version as i32 * 4 + 17, code.width);
+        }
+    }
+
+    let data = Code::encode_text("Grüße", EccLevel::M)
+        .unwrap()
+        .decode()
+        .unwrap();
+    assert_eq!(data.payload, "Grüße".as_bytes());
+    assert_eq!(data.eci, Some(quircs::Eci::Utf8));
+
+    let segments = [
+        Segment::new(DataType::Alpha, "ITEM "),
+        Segment::new(DataType::Numeric, "1234567"),
+        Segment::new(DataType::Kanji, vec![0x93, 0x5f, 0xe4, 0xaa]),
+    ];
+    let data = Code::encode_segments(&segments, EccLevel::Q)
+        .unwrap()
+        .decode()
+        .unwrap();
+    let modes: Vec<_> = data.segments.iter().map(|s| s.mode).collect();
+    assert_eq!(modes, [DataType::Alpha, DataType::Numeric, DataType::Kanji]);
+    assert!(Code::encode_segments(&[Segment::new(DataType::Numeric, "12a")], EccLevel::L).is_err());
+    assert!(matches!(
+        Code::encode(&[0; 3000], EccLevel::H),
+        Err(quircs::EncodeError::DataTooLong)
+    ));
+
+    // Encoded codes can be scanned again.
+    let code = Code::encode(b"scan me again", EccLevel::M).unwrap();
+    let image = code.to_luma(4, 4);
+    let mut q = Quirc::default();
+    let codes: Vec<_> = q
+        .identify(image.width, image.height, &image.pixels)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(codes.len(), 1);
+    assert_eq!(codes[0].decode().unwrap().payload, b"scan me again");
+}
+
+#[test]
+fn segment_optimization() {
+    use quircs::{Code, DataType, EccLevel, Segment};
+
+    let modes = |segments: &[Segment]| segments.iter().map(|s| s.mode).collect::<Vec<_>>();
+
+    let segments = Segment::optimize(b"ABC123456789xyz", 1);
+    assert_eq!(
+        modes(&segments),
+        [DataType::Alpha, DataType::Numeric, DataType::Byte]
+    );
+
+    // Short runs of digits aren't worth a segment of their own, which
+    // depends on the size of the character counts.
+    assert_eq!(
+        modes(&Segment::optimize(b"abc1234def", 1)),
+        [DataType::Byte]
+    );
+    let digits = format!("{}{}{}", "a".repeat(20), "1".repeat(7), "b".repeat(20));
+    assert_eq!(
+        modes(&Segment::optimize(digits.as_bytes(), 9)),
+        [DataType::Byte, DataType::Numeric, DataType::Byte]
+    );
+    assert_eq!(
+        modes(&Segment::optimize(digits.as_bytes(), 10)),
+        [DataType::Byte]
+    );
+
+    // Shift-JIS Kanji, with Latin parts.
+    let sjis = [
+        b'Q', b'R', 0x83, 0x52, 0x81, 0x5b, 0x83, 0x68, b'1', b'2', b'3',
+    ];
+    assert_eq!(
+        modes(&Segment::optimize(&sjis, 1)),
+        [DataType::Alpha, DataType::Kanji, DataType::Numeric]
+    );
+
+    // UTF-8 text only needs an ECI designator for the segments after
+    // the first character which isn't ASCII.
+    let segments = Segment::optimize_text("0123456789 ÄÖÜ", 1);
+    assert_eq!(segments[0].eci, None);
+    assert_eq!(segments.last().unwrap().eci, Some(quircs::Eci::Utf8));
+
+    // Mixed content fits a smaller version than in a single mode.
+    let text = "ABCDEFGHIJKLMNOP0123456789012345678901234567890123456789xyz";
+    let mixed = Code::encode(text.as_bytes(), EccLevel::M).unwrap();
+    let single = Code::encode_segments(&[Segment::new(DataType::Byte, text)], EccLevel::M).unwrap();
+    assert!(mixed.width < single.width);
+    assert_eq!(mixed.decode().unwrap().payload, text.as_bytes());
+}
+
+#[cfg(feature = "text")]
+#[test]
+fn kanji_text_encoding() {
+    use quircs::{Code, DataType, EccLevel, Segment};
+
+    let text = "QRコード (ABC-123)";
+    let segments = Segment::optimize_text(text, 1);
+    assert!(segments.iter().any(|s| s.mode == DataType::Kanji));
+    assert!(segments.iter().all(|s| s.eci.is_none()));
+
+    let data = Code::encode_text(text, EccLevel::M)
+        .unwrap()
+        .decode()
+        .unwrap();
+    assert_eq!(data.text().text, text);
+}
+
+#[test]
+fn render_code() {
+    use quircs::{Code, EccLevel};
+
+    let code = Code::encode(b"render me", EccLevel::Q).unwrap();
+    assert_eq!(code.width, 21);
+
+    // The raster can be scanned again.
+    let luma = code.to_luma(3, 4);
+    assert_eq!((luma.width, luma.height), (87, 87));
+    let mut q = Quirc::default();
+    let codes: Vec<_> = q
+        .identify(luma.width, luma.height, &luma.pixels)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(codes.len(), 1);
+    assert_eq!(codes[0].decode().unwrap().payload, b"render me");
+
+    // The extracted code renders as the decoder sampled it, with the
+    // cells outside the image in gray.
+    let image = render_qrcode(&qrcode::QrCode::new(b"cropped").unwrap(), 4, 4, None);
+    let cropped =
+        image::imageops::crop_imm(&image, 0, 0, image.width() - 24, image.height()).to_image();
+    let mut q = Quirc::default();
+    let code = q
+        .identify(
+            cropped.width() as usize,
+            cropped.height() as usize,
+            &cropped,
+        )
+        .next()
+        .unwrap()
+        .unwrap();
+    let luma = code.to_luma(1, 0);
+    assert_eq!(luma.pixels[20], 128);
+    assert_eq!(luma.pixels[0], 0);
+
+    let svg = code.to_svg(10, 4);
+    assert!(svg.starts_with("<svg "));
+    assert!(svg.contains("width=\"290\" height=\"290\""));
+    assert!(svg.contains("fill=\"#808080\""));
+    assert!(svg.trim_end().ends_with("</svg>"));
+
+    // Two rows of cells per line, with the quiet zone as blocks.
+    let text = Code::encode(b"terminal", EccLevel::L)
+        .unwrap()
+        .to_unicode(1, false);
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 12);
+    assert!(lines.iter().all(|line| line.chars().count() == 23));
+    assert!(lines[0].chars().all(|c| c == '█' || c == '▀'));
+    assert!(lines[0].starts_with("█▀▀▀▀▀▀▀█"));
+}
+
+#[test]
+fn debug_image() {
+    let image = render_qrcode(&qrcode::QrCode::new(b"debug me").unwrap(), 4, 4, None);
+    let mut q = Quirc::default();
+    let count = q
+        .identify(image.width() as usize, image.height() as usize, &image)
+        .count();
+    assert_eq!(count, 1);
+
+    let debug = q.debug_image();
+    assert_eq!((debug.width, debug.height), (q.w, q.h));
+    assert_eq!(debug.pixels.len(), q.w * q.h * 3);
+
+    let has = |color: [u8; 3]| debug.pixels.chunks(3).any(|p| p == color);
+    // Capstone corners, centres, grouping, alignment estimate and
+    // timing scans.
+    for color in [
+        [255, 0, 0],
+        [0, 0, 255],
+        [0, 192, 0],
+        [255, 0, 255],
+        [255, 160, 0],
+    ] {
+        assert!(has(color), "missing {:?}", color);
+    }
+
+    // Cells of the top left capstone are marked with the value read.
+    let qr = &q.grids[0];
+    let pixel = |u: f64, v: f64| {
+        let den = qr.c[6] * u + qr.c[7] * v + 1.0;
+        let x = ((qr.c[0] * u + qr.c[1] * v + qr.c[2]) / den).round() as usize;
+        let y = ((qr.c[3] * u + qr.c[4] * v + qr.c[5]) / den).round() as usize;
+        let i = (y * debug.width + x) * 3;
+        [debug.pixels[i], debug.pixels[i + 1], debug.pixels[i + 2]]
+    };
+    assert_eq!(pixel(2.5, 2.5), [0, 96, 96]);
+    assert_eq!(pixel(1.5, 1.5), [0, 255, 255]);
+
+    // rMQR grids have no timing scans or alignment estimate.
+    let segments = [quircs::Segment::new(quircs::DataType::Byte, "debug me")];
+    let code = quircs::Code::encode_rmqr(&segments, 10, quircs::EccLevel::M).unwrap();
+    let luma = code.to_luma(4, 2);
+    let count = q.identify(luma.width, luma.height, &luma.pixels).count();
+    assert_eq!(count, 1);
+    let debug = q.debug_image();
+    let has = |color: [u8; 3]| debug.pixels.chunks(3).any(|p| p == color);
+    assert!(!has([255, 0, 255]));
+    assert!(!has([255, 160, 0]));
+}
+
+#[test]
+fn rejection_diagnostics() {
+    use qrcode::{EcLevel, QrCode, Version};
+    use quircs::{DetectorConfig, Rejection};
+
+    // Wipe the alignment pattern of a version 2 code.
+    let scale = 4;
+    let code = QrCode::with_version(b"no alignment", Version::Normal(2), EcLevel::H).unwrap();
+    let mut image = render_qrcode(&code, scale, 4, None);
+    for y in (16 + 4) * scale..(21 + 4) * scale {
+        for x in (16 + 4) * scale..(21 + 4) * scale {
+            image.put_pixel(x, y, image::Luma([255]));
+        }
+    }
+    let (width, height) = (image.width() as usize, image.height() as usize);
+
+    // Nothing is recorded unless asked for.
+    let mut q = Quirc::default();
+    assert_eq!(q.identify(width, height, &image).count(), 1);
+    assert!(q.rejections().is_empty());
+
+    q.config = DetectorConfig {
+        diagnostics: true,
+        ..Default::default()
+    };
+    assert_eq!(q.identify(width, height, &image).count(), 1);
+    let (caps, grid_size) = q
+        .rejections()
+        .iter()
+        .find_map(|r| match r {
+            Rejection::AlignmentFallback {
+                caps, grid_size, ..
+            } => Some((*caps, *grid_size)),
+            _ => None,
+        })
+        .expect("no alignment fallback");
+    assert_eq!(caps, q.grids[0].caps);
+    assert_eq!(grid_size, 25);
+
+    // A lone finder pattern has no neighbours to group with, and one
+    // whose stone is a thin bar has the wrong ratio of areas.
+    let mut lone = image::GrayImage::from_pixel(100, 60, image::Luma([255]));
+    for y in 0..28 {
+        for x in 0..28 {
+            let d = (x as i32 / 4 - 3).abs().max((y as i32 / 4 - 3).abs());
+            if d != 2 {
+                lone.put_pixel(x + 16, y + 16, image::Luma([0]));
+            }
+            if d == 3 || (d < 2 && y == 14) {
+                lone.put_pixel(x + 56, y + 16, image::Luma([0]));
+            }
+        }
+    }
+    q.identify(100, 60, &lone);
+    assert_eq!(q.capstones.len(), 1);
+    assert!(q.rejections().iter().any(|r| matches!(
+        r,
+        Rejection::NotOrthogonal {
+            capstone: 0,
+            horizontal: 0,
+            vertical: 0,
+            ..
+        }
+    )));
+    assert!(q.rejections().iter().any(|r| matches!(
+        r,
+        Rejection::RingStoneRatio { position, ratio, .. } if position.x >= 56 && *ratio < 10
+    )));
 }